@@ -0,0 +1,149 @@
+use crate::span::Span;
+use crate::types::Type;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    /// Left shift. Only produced by folding, which strength-reduces
+    /// multiplications by two.
+    Shl,
+}
+
+impl BinOp {
+    /// Evaluates `lhs op rhs` with the wrap-around behaviour of the generated
+    /// code. Returns `None` for a division that would trap.
+    pub fn eval(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            BinOp::Add => Some(lhs.wrapping_add(rhs)),
+            BinOp::Sub => Some(lhs.wrapping_sub(rhs)),
+            BinOp::Mul => Some(lhs.wrapping_mul(rhs)),
+            BinOp::Div => lhs.checked_div(rhs),
+            BinOp::Eq => Some((lhs == rhs) as i64),
+            BinOp::Ne => Some((lhs != rhs) as i64),
+            BinOp::Lt => Some((lhs < rhs) as i64),
+            BinOp::Le => Some((lhs <= rhs) as i64),
+            // Like `shl`, only the low six bits of the count matter.
+            BinOp::Shl => Some(lhs.wrapping_shl(rhs as u32)),
+        }
+    }
+
+    pub fn is_commutative(self) -> bool {
+        matches!(self, BinOp::Add | BinOp::Mul | BinOp::Eq | BinOp::Ne)
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Shl => "<<",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Num {
+        value: i64,
+        span: Span,
+    },
+    Str {
+        value: String,
+        span: Span,
+    },
+    /// A variable reference. `id` indexes `Program::locals` once semantic
+    /// analysis has resolved the name.
+    Var {
+        name: String,
+        id: Option<usize>,
+        span: Span,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    Assign {
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Num { span, .. }
+            | Expr::Str { span, .. }
+            | Expr::Var { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Assign { span, .. } => *span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Expr::Num { span, .. }
+            | Expr::Str { span, .. }
+            | Expr::Var { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Assign { span, .. } => span,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Stmt {
+    Expr {
+        expr: Expr,
+    },
+    /// `span` runs from the `return` keyword to the end of `expr`.
+    Return {
+        expr: Expr,
+        span: Span,
+    },
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr { expr } => expr.span(),
+            Stmt::Return { span, .. } => *span,
+        }
+    }
+}
+
+/// A local variable. It is declared by the first assignment to its name and
+/// takes the type of the assigned value.
+#[derive(Debug)]
+pub struct LVar {
+    pub name: String,
+    pub ty: Type,
+    pub span: Span,
+    /// Distance below the frame pointer, assigned by `layout`.
+    pub offset: usize,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    /// Filled in by semantic analysis.
+    pub locals: Vec<LVar>,
+}
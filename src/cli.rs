@@ -0,0 +1,360 @@
+//! Command-line parsing for the driver. Options follow gcc: `-o`, `-l` and
+//! `-L` take their argument either attached or as the next argument, and
+//! `-` names stdin as an input and stdout as an output.
+
+use nine_cc::dump::Format;
+use nine_cc::opt::OptLevel;
+use nine_cc::Options;
+use std::path::Path;
+
+pub const USAGE: &str = "usage: nine-cc [options] <file>...";
+
+pub const HELP: &str = "\
+Options:
+  -S                   Compile only; do not assemble or link
+  -c                   Compile and assemble, but do not link
+  --emit=<kind>        Stop after writing <kind>: ir for the optimized IR,
+                       dot for its control-flow graph in Graphviz format,
+                       asm as with -S or obj as with -c
+  -fno-integrated-as   Assemble with the system assembler instead of writing
+                       object files directly
+  -fuse-ld=<linker>    Link with builtin, which needs no system tools or
+                       libraries, or bfd, the system linker run through
+                       $CC (default cc)
+  -o <file>            Write the output to <file>; '-' means stdout
+  -l <library>         Link with <library>
+  -L <dir>             Add <dir> to the library search path
+  -O0, -O1, -O2        Set the optimization level (-O is -O1)
+  -Wunreachable-code   Warn about code that will never be executed
+  --run                Interpret the input instead of compiling it, and exit
+                       with the value main returns
+  --jit                Compile the input and run it in memory, and exit with
+                       the value main returns
+  --stats              Report instruction counts around the peephole optimizer
+  --dump-tokens[=text|json]
+                       Print the tokens of each input and stop
+  --dump-ast[=text|json]
+                       Print the checked syntax tree of each input and stop
+  --help               Display this information
+  --version            Display the compiler version
+
+An input file named '-' is read from stdin, and files ending in .o or .a
+are passed to the linker. Without -o, -S writes foo.s for each foo.c in
+the current directory (stdout for stdin), --emit=ir and --emit=dot write
+foo.ir and foo.dot the same way, -c writes foo.o, and linking writes a.out.
+";
+
+/// Where compilation stops, as chosen by `-S`, `-c` and `--emit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Ir,
+    /// The control-flow graph of the IR, for Graphviz.
+    Dot,
+    Assembly,
+    Object,
+    Executable,
+}
+
+impl Stage {
+    /// Whether the compiler writes the output itself, as text.
+    pub fn is_text(self) -> bool {
+        matches!(self, Stage::Ir | Stage::Dot | Stage::Assembly)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Stdin,
+    File(String),
+    /// An object file or archive, only used when linking.
+    Object(String),
+}
+
+impl Input {
+    /// The name diagnostics refer to the input by.
+    pub fn name(&self) -> &str {
+        match self {
+            Input::Stdin => "<stdin>",
+            Input::File(path) | Input::Object(path) => path,
+        }
+    }
+
+    /// Where the output of `stage` goes when no `-o` is given: for
+    /// `dir/foo.c`, `foo.s`, `foo.o` and so on in the current directory.
+    /// Text for stdin goes to stdout, and an object to `-.o`.
+    pub fn default_output(&self, stage: Stage) -> Output {
+        let extension = match stage {
+            Stage::Ir => "ir",
+            Stage::Dot => "dot",
+            Stage::Assembly => "s",
+            Stage::Object => "o",
+            Stage::Executable => return Output::File("a.out".to_string()),
+        };
+        let stem = match self {
+            Input::Stdin if stage.is_text() => return Output::Stdout,
+            Input::Stdin => "-".into(),
+            Input::File(path) | Input::Object(path) => Path::new(path)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        };
+        Output::File(format!("{}.{}", stem, extension))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Stdout,
+    File(String),
+}
+
+/// What to do with the inputs of one run.
+#[derive(Debug)]
+pub struct Build {
+    pub options: Options,
+    /// Report instruction counts around the peephole optimizer.
+    pub stats: bool,
+    /// Interpret the input instead of compiling it.
+    pub run: bool,
+    /// Run the compiled input in-process instead of writing it out.
+    pub jit: bool,
+    /// Write object files directly rather than running `as`.
+    pub integrated_as: bool,
+    /// Link with the built-in linker rather than `$CC`.
+    pub builtin_ld: bool,
+    /// Dump the front end's output instead of compiling.
+    pub dump_tokens: Option<Format>,
+    pub dump_ast: Option<Format>,
+    pub stage: Stage,
+    pub inputs: Vec<Input>,
+    pub output: Option<Output>,
+    /// The `-l` and `-L` options for the linker, in order.
+    pub link_args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    Version,
+    Build(Build),
+}
+
+/// Parses the arguments that follow the program name. An error is a
+/// message for the user, to be reported as `nine-cc: error: <message>`.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut build = Build {
+        options: Options::default(),
+        stats: false,
+        run: false,
+        jit: false,
+        integrated_as: true,
+        builtin_ld: false,
+        dump_tokens: None,
+        dump_ast: None,
+        stage: Stage::Executable,
+        inputs: vec![],
+        output: None,
+        link_args: vec![],
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            "-O0" => build.options.level = OptLevel::O0,
+            "-O" | "-O1" => build.options.level = OptLevel::O1,
+            "-O2" => build.options.level = OptLevel::O2,
+            "--stats" => build.stats = true,
+            "--run" => build.run = true,
+            "--jit" => build.jit = true,
+            "--dump-tokens" | "--dump-tokens=text" => build.dump_tokens = Some(Format::Text),
+            "--dump-tokens=json" => build.dump_tokens = Some(Format::Json),
+            "--dump-ast" | "--dump-ast=text" => build.dump_ast = Some(Format::Text),
+            "--dump-ast=json" => build.dump_ast = Some(Format::Json),
+            "-Wunreachable-code" => build.options.warn_unreachable = true,
+            "-S" | "--emit=asm" => build.stage = Stage::Assembly,
+            "-c" | "--emit=obj" => build.stage = Stage::Object,
+            "--emit=ir" => build.stage = Stage::Ir,
+            "--emit=dot" => build.stage = Stage::Dot,
+            "-fintegrated-as" => build.integrated_as = true,
+            "-fno-integrated-as" => build.integrated_as = false,
+            "-fuse-ld=builtin" => build.builtin_ld = true,
+            "-fuse-ld=bfd" => build.builtin_ld = false,
+            "-o" => match args.next() {
+                Some(path) => build.output = Some(output_path(path)),
+                None => return Err("missing filename after '-o'".to_string()),
+            },
+            "-l" | "-L" => match args.next() {
+                Some(value) => build.link_args.push(format!("{}{}", arg, value)),
+                None => return Err(format!("argument to '{}' is missing", arg)),
+            },
+            "-" => build.inputs.push(Input::Stdin),
+            _ if arg.starts_with("-o") => build.output = Some(output_path(&arg[2..])),
+            _ if arg.starts_with("-l") || arg.starts_with("-L") => {
+                build.link_args.push(arg.clone())
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unrecognized command-line option '{}'", arg))
+            }
+            _ if arg.ends_with(".o") || arg.ends_with(".a") => {
+                build.inputs.push(Input::Object(arg.clone()))
+            }
+            _ => build.inputs.push(Input::File(arg.clone())),
+        }
+    }
+    if build.inputs.is_empty() {
+        return Err("no input files".to_string());
+    }
+    for (flag, set) in [("--run", build.run), ("--jit", build.jit)] {
+        if set && !matches!(build.inputs[..], [Input::Stdin] | [Input::File(_)]) {
+            return Err(format!("'{}' needs exactly one input file", flag));
+        }
+    }
+    if build.builtin_ld && !build.link_args.is_empty() {
+        return Err("'-fuse-ld=builtin' cannot link libraries".to_string());
+    }
+    if build.output.is_some() && build.inputs.len() > 1 && build.stage != Stage::Executable {
+        return Err("cannot specify '-o' with '-c' or '-S' with multiple files".to_string());
+    }
+    Ok(Command::Build(build))
+}
+
+fn output_path(path: &str) -> Output {
+    match path {
+        "-" => Output::Stdout,
+        _ => Output::File(path.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse, Build, Command, Input, Output, Stage};
+    use nine_cc::dump::Format;
+    use nine_cc::opt::OptLevel;
+
+    fn parsed(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse(&args)
+    }
+
+    fn build(args: &[&str]) -> Build {
+        match parsed(args).unwrap() {
+            Command::Build(build) => build,
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn inputs_and_output() {
+        let compile = build(&["-O2", "-S", "src/foo.c", "-o", "out.s"]);
+        assert_eq!(compile.options.level, OptLevel::O2);
+        assert_eq!(compile.stage, Stage::Assembly);
+        assert_eq!(compile.inputs, vec![Input::File("src/foo.c".to_string())]);
+        assert_eq!(compile.output, Some(Output::File("out.s".to_string())));
+        assert!(compile.integrated_as);
+        assert!(!build(&["-c", "-fno-integrated-as", "a.c"]).integrated_as);
+
+        assert_eq!(build(&["--emit=ir", "a.c"]).stage, Stage::Ir);
+        assert_eq!(build(&["--emit=dot", "a.c"]).stage, Stage::Dot);
+        assert!(build(&["--run", "a.c"]).run);
+        assert!(build(&["--jit", "-"]).jit);
+
+        let stdin = build(&["-", "-o-"]);
+        assert_eq!(stdin.inputs, vec![Input::Stdin]);
+        assert_eq!(stdin.output, Some(Output::Stdout));
+    }
+
+    #[test]
+    fn linking() {
+        let link = build(&["a.c", "b.o", "-lm", "-L", "lib", "-l", "z", "-o", "prog"]);
+        assert_eq!(link.stage, Stage::Executable);
+        assert_eq!(
+            link.inputs,
+            vec![
+                Input::File("a.c".to_string()),
+                Input::Object("b.o".to_string())
+            ]
+        );
+        assert_eq!(link.output, Some(Output::File("prog".to_string())));
+        assert_eq!(link.link_args, vec!["-lm", "-Llib", "-lz"]);
+        assert!(!link.builtin_ld);
+        assert!(build(&["-fuse-ld=builtin", "a.c"]).builtin_ld);
+    }
+
+    #[test]
+    fn default_outputs() {
+        let file = Input::File("src/foo.c".to_string());
+        assert_eq!(
+            file.default_output(Stage::Assembly),
+            Output::File("foo.s".to_string())
+        );
+        assert_eq!(
+            file.default_output(Stage::Object),
+            Output::File("foo.o".to_string())
+        );
+        assert_eq!(
+            file.default_output(Stage::Executable),
+            Output::File("a.out".to_string())
+        );
+        assert_eq!(
+            file.default_output(Stage::Dot),
+            Output::File("foo.dot".to_string())
+        );
+        assert_eq!(Input::Stdin.default_output(Stage::Assembly), Output::Stdout);
+        assert_eq!(Input::Stdin.default_output(Stage::Ir), Output::Stdout);
+        assert_eq!(
+            Input::Stdin.default_output(Stage::Object),
+            Output::File("-.o".to_string())
+        );
+    }
+
+    #[test]
+    fn dumps() {
+        let dump = build(&["--dump-tokens", "--dump-ast=json", "a.c"]);
+        assert_eq!(dump.dump_tokens, Some(Format::Text));
+        assert_eq!(dump.dump_ast, Some(Format::Json));
+        assert_eq!(
+            parsed(&["--dump-ast=xml", "a.c"]).unwrap_err(),
+            "unrecognized command-line option '--dump-ast=xml'"
+        );
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(parsed(&["a.c", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parsed(&["--version"]), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parsed(&[]).unwrap_err(), "no input files");
+        assert_eq!(
+            parsed(&["a.c", "-o"]).unwrap_err(),
+            "missing filename after '-o'"
+        );
+        assert_eq!(
+            parsed(&["a.c", "-O3"]).unwrap_err(),
+            "unrecognized command-line option '-O3'"
+        );
+        assert_eq!(
+            parsed(&["-c", "a.c", "b.c", "-o", "out.o"]).unwrap_err(),
+            "cannot specify '-o' with '-c' or '-S' with multiple files"
+        );
+        assert_eq!(
+            parsed(&["a.c", "-l"]).unwrap_err(),
+            "argument to '-l' is missing"
+        );
+        assert_eq!(
+            parsed(&["--run", "a.c", "b.c"]).unwrap_err(),
+            "'--run' needs exactly one input file"
+        );
+        assert_eq!(
+            parsed(&["-fuse-ld=builtin", "a.c", "-lc"]).unwrap_err(),
+            "'-fuse-ld=builtin' cannot link libraries"
+        );
+        assert_eq!(
+            parsed(&["--jit", "b.o"]).unwrap_err(),
+            "'--jit' needs exactly one input file"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use crate::span::{SourceMap, Span};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A secondary location attached to a diagnostic, e.g. a previous
+/// declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, span, message)
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic in the gcc style:
+    ///
+    /// ```text
+    /// file.c:3:7: error: expected ';'
+    ///     3 | a = 1 b
+    ///       |       ^
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = String::new();
+        render_location(&mut out, sources, self.span, self.severity, &self.message);
+        render_excerpt(&mut out, sources, self.span, '^');
+        for label in &self.labels {
+            render_location(
+                &mut out,
+                sources,
+                label.span,
+                Severity::Note,
+                &label.message,
+            );
+            render_excerpt(&mut out, sources, label.span, '~');
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{}: {}\n", Severity::Note, note));
+        }
+        out
+    }
+}
+
+fn render_location(
+    out: &mut String,
+    sources: &SourceMap,
+    span: Span,
+    severity: Severity,
+    message: &str,
+) {
+    let file = sources.get(span.file);
+    out.push_str(&format!(
+        "{}:{}:{}: {}: {}\n",
+        file.name, span.line, span.column, severity, message
+    ));
+}
+
+/// Prints the line `span` starts on and underlines the spanned columns. The
+/// first underlined column is marked with `first`, the rest with `~`.
+fn render_excerpt(out: &mut String, sources: &SourceMap, span: Span, first: char) {
+    let line = sources.get(span.file).line(span.line);
+    let start = (span.column - 1).min(line.len());
+    let end = (start + span.len).min(line.len()).max(start + 1);
+    let padding: String = line[..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let mut underline = first.to_string();
+    underline.push_str(&"~".repeat(end - start - 1));
+    out.push_str(&format!("{:>5} | {}\n", span.line, line));
+    out.push_str(&format!("{:>5} | {}{}\n", "", padding, underline));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::Diagnostic;
+    use crate::span::{SourceMap, Span};
+
+    #[test]
+    fn render() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("foo.c".to_string(), "a = 1;\nb = a +  ;\n".to_string());
+        let diagnostic = Diagnostic::error(Span::new(file, 16, 1, 2, 10), "expected expression")
+            .with_label(Span::new(file, 7, 5, 2, 1), "in this assignment")
+            .with_note("operands must be expressions");
+        assert_eq!(
+            diagnostic.render(&sources),
+            "foo.c:2:10: error: expected expression\n\
+             \x20   2 | b = a +  ;\n\
+             \x20     |          ^\n\
+             foo.c:2:1: note: in this assignment\n\
+             \x20   2 | b = a +  ;\n\
+             \x20     | ~~~~~\n\
+             note: operands must be expressions\n"
+        );
+    }
+
+    #[test]
+    fn render_underlines_whole_span() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("<input>".to_string(), "\t1 = 2;".to_string());
+        let diagnostic = Diagnostic::error(Span::new(file, 1, 5, 1, 2), "lvalue required");
+        assert_eq!(
+            diagnostic.render(&sources),
+            "<input>:1:2: error: lvalue required\n\
+             \x20   1 | \t1 = 2;\n\
+             \x20     | \t^~~~~\n"
+        );
+    }
+
+    #[test]
+    fn render_at_end_of_input() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("<input>".to_string(), "1 + 2".to_string());
+        let diagnostic = Diagnostic::error(Span::new(file, 5, 0, 1, 6), "expected ';'");
+        assert_eq!(
+            diagnostic.render(&sources),
+            "<input>:1:6: error: expected ';'\n\
+             \x20   1 | 1 + 2\n\
+             \x20     |      ^\n"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Dumps of the token stream and the AST, for looking at what the front end
+//! produced. The text format is an indented tree for people; the JSON
+//! format carries full spans for tools.
+
+use crate::ast::{Expr, LVar, Program, Stmt};
+use crate::span::Span;
+use crate::token::{Token, TokenKind};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Dumps `tokens`, one per line in text form or as a JSON array.
+pub fn tokens(tokens: &[Token], format: Format) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Text => {
+            for token in tokens {
+                let (kind, text) = describe(&token.kind);
+                let location = format!("{}:{}", token.span.line, token.span.column);
+                let _ = writeln!(out, "{:<8}{:<8}{}", location, kind, text);
+            }
+        }
+        Format::Json => {
+            let tokens: Vec<String> = tokens
+                .iter()
+                .map(|token| {
+                    let (kind, text) = describe(&token.kind);
+                    let value = match &token.kind {
+                        TokenKind::Num(value) => value.to_string(),
+                        TokenKind::Str(value) => json_string(value),
+                        _ => json_string(&text),
+                    };
+                    format!(
+                        "{{\"kind\":{},\"value\":{},\"span\":{}}}",
+                        json_string(kind),
+                        value,
+                        json_span(token.span)
+                    )
+                })
+                .collect();
+            let _ = writeln!(out, "[{}]", tokens.join(","));
+        }
+    }
+    out
+}
+
+/// The kind of a token and its text as it would be written in a dump.
+fn describe(kind: &TokenKind) -> (&'static str, String) {
+    match kind {
+        TokenKind::Keyword(keyword) => ("keyword", keyword.to_string()),
+        TokenKind::Ident(name) => ("ident", name.clone()),
+        TokenKind::Punct(punct) => ("punct", punct.to_string()),
+        TokenKind::Num(value) => ("num", value.to_string()),
+        TokenKind::Str(value) => ("str", format!("{:?}", value)),
+        TokenKind::Eof => ("eof", String::new()),
+    }
+}
+
+/// Dumps a checked `program`: its locals, then each statement as a tree.
+pub fn ast(program: &Program, format: Format) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Text => {
+            out.push_str("Program\n");
+            for (id, lvar) in program.locals.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "  Local #{} {} '{}' {}",
+                    id,
+                    lvar.name,
+                    lvar.ty,
+                    text_span(lvar.span)
+                );
+            }
+            for stmt in &program.stmts {
+                text_stmt(&mut out, stmt);
+            }
+        }
+        Format::Json => {
+            let locals: Vec<String> = program.locals.iter().map(json_local).collect();
+            let stmts: Vec<String> = program.stmts.iter().map(json_stmt).collect();
+            let _ = writeln!(
+                out,
+                "{{\"locals\":[{}],\"stmts\":[{}]}}",
+                locals.join(","),
+                stmts.join(",")
+            );
+        }
+    }
+    out
+}
+
+fn text_span(span: Span) -> String {
+    format!("<{}:{}>", span.line, span.column)
+}
+
+fn text_stmt(out: &mut String, stmt: &Stmt) {
+    let (name, expr) = match stmt {
+        Stmt::Expr { expr } => ("ExprStmt", expr),
+        Stmt::Return { expr, .. } => ("ReturnStmt", expr),
+    };
+    let _ = writeln!(out, "  {} {}", name, text_span(stmt.span()));
+    text_expr(out, expr, 2);
+}
+
+fn text_expr(out: &mut String, expr: &Expr, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let span = text_span(expr.span());
+    match expr {
+        Expr::Num { value, .. } => {
+            let _ = writeln!(out, "{}Num {} {}", indent, value, span);
+        }
+        Expr::Str { value, .. } => {
+            let _ = writeln!(out, "{}Str {:?} {}", indent, value, span);
+        }
+        Expr::Var { name, id, .. } => {
+            let id = match id {
+                Some(id) => format!(" #{}", id),
+                None => String::new(),
+            };
+            let _ = writeln!(out, "{}Var {}{} {}", indent, name, id, span);
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            let _ = writeln!(out, "{}Binary '{}' {}", indent, op, span);
+            text_expr(out, lhs, depth + 1);
+            text_expr(out, rhs, depth + 1);
+        }
+        Expr::Assign { lhs, rhs, .. } => {
+            let _ = writeln!(out, "{}Assign {}", indent, span);
+            text_expr(out, lhs, depth + 1);
+            text_expr(out, rhs, depth + 1);
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_span(span: Span) -> String {
+    format!(
+        "{{\"file\":{},\"offset\":{},\"len\":{},\"line\":{},\"column\":{}}}",
+        span.file.0, span.offset, span.len, span.line, span.column
+    )
+}
+
+fn json_local(lvar: &LVar) -> String {
+    format!(
+        "{{\"name\":{},\"type\":{},\"span\":{}}}",
+        json_string(&lvar.name),
+        json_string(&lvar.ty.to_string()),
+        json_span(lvar.span)
+    )
+}
+
+fn json_stmt(stmt: &Stmt) -> String {
+    let (kind, expr) = match stmt {
+        Stmt::Expr { expr } => ("expr", expr),
+        Stmt::Return { expr, .. } => ("return", expr),
+    };
+    format!(
+        "{{\"kind\":\"{}\",\"expr\":{},\"span\":{}}}",
+        kind,
+        json_expr(expr),
+        json_span(stmt.span())
+    )
+}
+
+fn json_expr(expr: &Expr) -> String {
+    let fields = match expr {
+        Expr::Num { value, .. } => format!("\"kind\":\"num\",\"value\":{}", value),
+        Expr::Str { value, .. } => format!("\"kind\":\"str\",\"value\":{}", json_string(value)),
+        Expr::Var { name, id, .. } => {
+            let id = match id {
+                Some(id) => id.to_string(),
+                None => "null".to_string(),
+            };
+            format!(
+                "\"kind\":\"var\",\"name\":{},\"id\":{}",
+                json_string(name),
+                id
+            )
+        }
+        Expr::Binary { op, lhs, rhs, .. } => format!(
+            "\"kind\":\"binary\",\"op\":\"{}\",\"lhs\":{},\"rhs\":{}",
+            op,
+            json_expr(lhs),
+            json_expr(rhs)
+        ),
+        Expr::Assign { lhs, rhs, .. } => format!(
+            "\"kind\":\"assign\",\"lhs\":{},\"rhs\":{}",
+            json_expr(lhs),
+            json_expr(rhs)
+        ),
+    };
+    format!("{{{},\"span\":{}}}", fields, json_span(expr.span()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dump::{ast, json_string, tokens, Format};
+    use crate::{analyze, parse, parse_syntax, tokenize, Options};
+
+    #[test]
+    fn token_text() {
+        let input = tokenize("return \"a\";", &Options::default()).unwrap();
+        assert_eq!(
+            tokens(&input, Format::Text),
+            "1:1     keyword return\n\
+             1:8     str     \"a\"\n\
+             1:11    punct   ;\n\
+             1:12    eof     \n"
+        );
+    }
+
+    #[test]
+    fn token_json() {
+        let input = tokenize("x 42", &Options::default()).unwrap();
+        assert_eq!(
+            tokens(&input[..2], Format::Json),
+            "[{\"kind\":\"ident\",\"value\":\"x\",\
+             \"span\":{\"file\":0,\"offset\":0,\"len\":1,\"line\":1,\"column\":1}},\
+             {\"kind\":\"num\",\"value\":42,\
+             \"span\":{\"file\":0,\"offset\":2,\"len\":2,\"line\":1,\"column\":3}}]\n"
+        );
+    }
+
+    #[test]
+    fn ast_text() {
+        let program = parse("a = 1;\nreturn a * (2 + 3);", &Options::default()).unwrap();
+        assert_eq!(
+            ast(&program, Format::Text),
+            "Program\n\
+             \x20 Local #0 a 'int' <1:1>\n\
+             \x20 ExprStmt <1:1>\n\
+             \x20   Assign <1:1>\n\
+             \x20     Var a #0 <1:1>\n\
+             \x20     Num 1 <1:5>\n\
+             \x20 ReturnStmt <2:1>\n\
+             \x20   Binary '*' <2:8>\n\
+             \x20     Var a #0 <2:8>\n\
+             \x20     Binary '+' <2:12>\n\
+             \x20       Num 2 <2:13>\n\
+             \x20       Num 3 <2:17>\n"
+        );
+    }
+
+    #[test]
+    fn ast_with_errors() {
+        // Variables that do not resolve have no id.
+        let mut program = parse_syntax("a = b;", &Options::default()).unwrap();
+        assert!(analyze(&mut program).is_err());
+        assert_eq!(
+            ast(&program, Format::Text),
+            "Program\n\
+             \x20 Local #0 a 'int' <1:1>\n\
+             \x20 ExprStmt <1:1>\n\
+             \x20   Assign <1:1>\n\
+             \x20     Var a #0 <1:1>\n\
+             \x20     Var b <1:5>\n"
+        );
+    }
+
+    #[test]
+    fn ast_json() {
+        let program = parse("return 7;", &Options::default()).unwrap();
+        assert_eq!(
+            ast(&program, Format::Json),
+            "{\"locals\":[],\"stmts\":[{\"kind\":\"return\",\
+             \"expr\":{\"kind\":\"num\",\"value\":7,\
+             \"span\":{\"file\":0,\"offset\":7,\"len\":1,\"line\":1,\"column\":8}},\
+             \"span\":{\"file\":0,\"offset\":0,\"len\":8,\"line\":1,\"column\":1}}]}\n"
+        );
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(json_string("a\"b\\\n\u{1}"), "\"a\\\"b\\\\\\n\\u0001\"");
+    }
+}
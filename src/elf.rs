@@ -0,0 +1,416 @@
+//! Writes ELF64 relocatable objects for x86-64 Linux, so that `-c` does not
+//! need the system assembler. The object has the sections `as` would give
+//! it: `.text`, `.data`, `.bss` and `.rodata`, a symbol table with a global
+//! symbol for each function and each function called but not defined, and
+//! `.rela.text` for the references from the code to the string literals
+//! and for the calls.
+
+use crate::encode::{self, Target};
+use crate::x86::Program;
+use std::collections::HashMap;
+
+pub const EHDR_SIZE: u16 = 64;
+const SHDR_SIZE: u16 = 64;
+const SYM_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+
+pub const ET_REL: u16 = 1;
+pub const EM_X86_64: u16 = 62;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+/// A 32-bit PC-relative reference, as to data.
+pub const R_X86_64_PC32: u32 = 2;
+/// A call, which the linker may route through the PLT.
+pub const R_X86_64_PLT32: u32 = 4;
+
+/// The section index of undefined symbols.
+const SHN_UNDEF: u16 = 0;
+
+/// The section indices, in the order of the section header table.
+const TEXT: u16 = 1;
+const DATA: u16 = 2;
+const BSS: u16 = 3;
+const RODATA: u16 = 4;
+const SYMTAB: u16 = 7;
+const STRTAB: u16 = 8;
+const SHSTRTAB: u16 = 9;
+const SECTION_COUNT: u16 = 10;
+
+/// The symbol table index of the section symbol of `.rodata`, which the
+/// relocations refer to. Section symbols come first, after the null symbol.
+const RODATA_SYMBOL: u64 = 4;
+
+/// Encodes `program` and returns it as the bytes of an object file.
+pub fn object(program: &Program) -> Result<Vec<u8>, String> {
+    let code = encode::encode(program)?;
+
+    let mut strtab = StringTable::new();
+    let mut symtab = vec![];
+    symbol(&mut symtab, 0, 0, 0, 0, 0);
+    for section in [TEXT, DATA, BSS, RODATA] {
+        symbol(&mut symtab, 0, STB_LOCAL << 4 | STT_SECTION, section, 0, 0);
+    }
+    let first_global = symtab.len() as u64 / SYM_SIZE;
+    // The symbol table index of each global, by name.
+    let mut globals = HashMap::new();
+    for (i, (name, offset)) in code.symbols.iter().enumerate() {
+        let end = match code.symbols.get(i + 1) {
+            Some((_, next)) => *next,
+            None => code.text.len(),
+        };
+        globals.insert(name.as_str(), symtab.len() as u64 / SYM_SIZE);
+        let info = STB_GLOBAL << 4 | STT_FUNC;
+        symbol(
+            &mut symtab,
+            strtab.add(name),
+            info,
+            TEXT,
+            *offset,
+            end - offset,
+        );
+    }
+
+    let mut rela = vec![];
+    for relocation in &code.relocations {
+        let (symbol_index, kind, addend) = match &relocation.target {
+            Target::Data(target) => (RODATA_SYMBOL, R_X86_64_PC32, *target as i64 - 4),
+            Target::Symbol(name) => {
+                let index = match globals.get(name.as_str()) {
+                    Some(index) => *index,
+                    None => {
+                        let index = symtab.len() as u64 / SYM_SIZE;
+                        let info = STB_GLOBAL << 4 | STT_NOTYPE;
+                        symbol(&mut symtab, strtab.add(name), info, SHN_UNDEF, 0, 0);
+                        globals.insert(name, index);
+                        index
+                    }
+                };
+                (index, R_X86_64_PLT32, -4)
+            }
+        };
+        rela.extend((relocation.offset as u64).to_le_bytes());
+        rela.extend((symbol_index << 32 | kind as u64).to_le_bytes());
+        rela.extend(addend.to_le_bytes());
+    }
+
+    let mut shstrtab = StringTable::new();
+    let mut sections = vec![Section::default()];
+    let mut add = |name: &str, section: Section| {
+        sections.push(Section {
+            name: shstrtab.add(name),
+            ..section
+        })
+    };
+    add(
+        ".text",
+        Section::progbits(code.text, SHF_ALLOC | SHF_EXECINSTR, 16),
+    );
+    add(".data", Section::progbits(vec![], SHF_WRITE | SHF_ALLOC, 8));
+    add(
+        ".bss",
+        Section {
+            kind: SHT_NOBITS,
+            flags: SHF_WRITE | SHF_ALLOC,
+            align: 8,
+            ..Section::default()
+        },
+    );
+    add(".rodata", Section::progbits(code.rodata, SHF_ALLOC, 1));
+    // Marks the stack as not executable.
+    add(".note.GNU-stack", Section::progbits(vec![], 0, 1));
+    add(
+        ".rela.text",
+        Section {
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            bytes: rela,
+            link: SYMTAB as u32,
+            info: TEXT as u32,
+            align: 8,
+            entry_size: RELA_SIZE,
+            ..Section::default()
+        },
+    );
+    add(
+        ".symtab",
+        Section {
+            kind: SHT_SYMTAB,
+            bytes: symtab,
+            link: STRTAB as u32,
+            info: first_global as u32,
+            align: 8,
+            entry_size: SYM_SIZE,
+            ..Section::default()
+        },
+    );
+    add(".strtab", Section::strtab(strtab.bytes));
+    let name = shstrtab.add(".shstrtab");
+    sections.push(Section {
+        name,
+        ..Section::strtab(shstrtab.bytes)
+    });
+    assert_eq!(sections.len(), SECTION_COUNT as usize);
+
+    Ok(write(&mut sections))
+}
+
+/// Lays out the contents of `sections` after the ELF header, followed by
+/// the section header table.
+fn write(sections: &mut [Section]) -> Vec<u8> {
+    let mut out = vec![0; EHDR_SIZE as usize];
+    for section in sections.iter_mut().skip(1) {
+        out.resize(section_start(out.len(), section.align), 0);
+        section.offset = out.len() as u64;
+        out.extend(&section.bytes);
+    }
+    out.resize(section_start(out.len(), 8), 0);
+    let section_headers = out.len() as u64;
+    for section in sections.iter() {
+        section.write_header(&mut out);
+    }
+
+    let mut header = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+    header.resize(16, 0);
+    header.extend(ET_REL.to_le_bytes());
+    header.extend(EM_X86_64.to_le_bytes());
+    header.extend(1u32.to_le_bytes());
+    header.extend(0u64.to_le_bytes()); // entry
+    header.extend(0u64.to_le_bytes()); // program headers
+    header.extend(section_headers.to_le_bytes());
+    header.extend(0u32.to_le_bytes()); // flags
+    header.extend(EHDR_SIZE.to_le_bytes());
+    header.extend(0u16.to_le_bytes()); // program header size
+    header.extend(0u16.to_le_bytes()); // program header count
+    header.extend(SHDR_SIZE.to_le_bytes());
+    header.extend(SECTION_COUNT.to_le_bytes());
+    header.extend(SHSTRTAB.to_le_bytes());
+    out[..EHDR_SIZE as usize].copy_from_slice(&header);
+    out
+}
+
+fn section_start(offset: usize, align: u64) -> usize {
+    offset.next_multiple_of(align.max(1) as usize)
+}
+
+/// Appends an `Elf64_Sym` to `symtab`.
+fn symbol(symtab: &mut Vec<u8>, name: u32, info: u8, section: u16, value: usize, size: usize) {
+    symtab.extend(name.to_le_bytes());
+    symtab.push(info);
+    symtab.push(0);
+    symtab.extend(section.to_le_bytes());
+    symtab.extend((value as u64).to_le_bytes());
+    symtab.extend((size as u64).to_le_bytes());
+}
+
+/// A string table, which starts with the empty string.
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable { bytes: vec![0] }
+    }
+
+    /// Adds `name` and returns its offset.
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+#[derive(Default)]
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    bytes: Vec<u8>,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+    /// Where `bytes` starts in the file, once laid out.
+    offset: u64,
+}
+
+impl Section {
+    fn progbits(bytes: Vec<u8>, flags: u64, align: u64) -> Self {
+        Section {
+            kind: SHT_PROGBITS,
+            flags,
+            bytes,
+            align,
+            ..Section::default()
+        }
+    }
+
+    fn strtab(bytes: Vec<u8>) -> Self {
+        Section {
+            kind: SHT_STRTAB,
+            bytes,
+            align: 1,
+            ..Section::default()
+        }
+    }
+
+    /// Appends the `Elf64_Shdr` of this section.
+    fn write_header(&self, out: &mut Vec<u8>) {
+        out.extend(self.name.to_le_bytes());
+        out.extend(self.kind.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        out.extend(0u64.to_le_bytes()); // address
+        out.extend(self.offset.to_le_bytes());
+        out.extend((self.bytes.len() as u64).to_le_bytes());
+        out.extend(self.link.to_le_bytes());
+        out.extend(self.info.to_le_bytes());
+        out.extend(self.align.to_le_bytes());
+        out.extend(self.entry_size.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::object;
+    use crate::x86::{Function, Inst, Program};
+    use crate::{compile, Options};
+    use std::convert::TryInto;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn c_string(bytes: &[u8], offset: usize) -> &str {
+        let end = bytes[offset..].iter().position(|byte| *byte == 0).unwrap();
+        std::str::from_utf8(&bytes[offset..offset + end]).unwrap()
+    }
+
+    /// The name, offset and size of each section of `elf`.
+    fn sections(elf: &[u8]) -> Vec<(String, usize, usize)> {
+        let headers = u64_at(elf, 40) as usize;
+        let count = u16_at(elf, 60) as usize;
+        let header = |i: usize| headers + 64 * i;
+        let names = u64_at(elf, header(u16_at(elf, 62) as usize) + 24) as usize;
+        (0..count)
+            .map(|i| {
+                let name = c_string(elf, names + u32_at(elf, header(i)) as usize);
+                let offset = u64_at(elf, header(i) + 24) as usize;
+                let size = u64_at(elf, header(i) + 32) as usize;
+                (name.to_string(), offset, size)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_objects() {
+        let output = compile("s = \"ab\"; t = \"c\"; t - s;", &Options::default()).unwrap();
+        let elf = object(&output.assembly).unwrap();
+        assert_eq!(&elf[..8], b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(u16_at(&elf, 16), 1);
+        assert_eq!(u16_at(&elf, 18), 62);
+
+        let sections = sections(&elf);
+        let names: Vec<&str> = sections.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "",
+                ".text",
+                ".data",
+                ".bss",
+                ".rodata",
+                ".note.GNU-stack",
+                ".rela.text",
+                ".symtab",
+                ".strtab",
+                ".shstrtab"
+            ]
+        );
+        let (_, rodata, size) = sections[4];
+        assert_eq!(&elf[rodata..rodata + size], b"ab\0c\0");
+
+        // Two references to the literals, at 0 and 3 bytes into .rodata.
+        let (_, rela, size) = sections[6];
+        assert_eq!(size, 48);
+        assert_eq!(u64_at(&elf, rela + 8), 4 << 32 | 2);
+        assert_eq!(u64_at(&elf, rela + 16) as i64, -4);
+        assert_eq!(u64_at(&elf, rela + 24 + 16) as i64, -1);
+
+        // `main` is the first global, after the null and section symbols.
+        let (_, symtab, size) = sections[7];
+        let (_, strtab, _) = sections[8];
+        assert_eq!(size, 6 * 24);
+        let main = symtab + 5 * 24;
+        assert_eq!(c_string(&elf, strtab + u32_at(&elf, main) as usize), "main");
+        assert_eq!(elf[main + 4], 0x12);
+        assert_eq!(u16_at(&elf, main + 6), 1);
+        assert_eq!(u64_at(&elf, main + 16) as usize, sections[1].2);
+    }
+
+    #[test]
+    fn writes_calls() {
+        let function = |name: &str, insts| Function {
+            name: name.to_string(),
+            insts,
+        };
+        let program = Program {
+            data: vec![],
+            functions: vec![
+                function("f", vec![Inst::Ret]),
+                function(
+                    "main",
+                    vec![
+                        Inst::Call("f".to_string()),
+                        Inst::Call("labs".to_string()),
+                        Inst::Ret,
+                    ],
+                ),
+            ],
+        };
+        let elf = object(&program).unwrap();
+        let sections = sections(&elf);
+
+        // Both calls are PLT32, against f and a new undefined symbol.
+        let (_, rela, size) = sections[6];
+        assert_eq!(size, 48);
+        assert_eq!(u64_at(&elf, rela), 2);
+        assert_eq!(u64_at(&elf, rela + 8), 5 << 32 | 4);
+        assert_eq!(u64_at(&elf, rela + 16) as i64, -4);
+        assert_eq!(u64_at(&elf, rela + 24), 7);
+        assert_eq!(u64_at(&elf, rela + 24 + 8), 7 << 32 | 4);
+        assert_eq!(u64_at(&elf, rela + 24 + 16) as i64, -4);
+
+        let (_, symtab, size) = sections[7];
+        let (_, strtab, _) = sections[8];
+        assert_eq!(size, 8 * 24);
+        let labs = symtab + 7 * 24;
+        assert_eq!(c_string(&elf, strtab + u32_at(&elf, labs) as usize), "labs");
+        assert_eq!(elf[labs + 4], 0x10);
+        assert_eq!(u16_at(&elf, labs + 6), 0);
+    }
+}
@@ -0,0 +1,523 @@
+//! Encodes the x86-64 instructions of `x86` into machine code, for running
+//! a program without going through the system assembler. Jumps always take
+//! a 32-bit displacement, so the code is a little larger than what `as`
+//! produces but needs only one pass.
+
+use crate::x86::{Cond, Inst, Operand, Program, Reg};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The machine code of a program and its read-only data. References from
+/// the code to the data and calls are left for whoever places the two in
+/// memory.
+#[derive(Debug)]
+pub struct Code {
+    pub text: Vec<u8>,
+    pub rodata: Vec<u8>,
+    /// Each function's name and its offset in `text`.
+    pub symbols: Vec<(String, usize)>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A 32-bit field at `offset` in `text` that must hold the distance from
+/// the end of the field to `target`. The field ends its instruction, a
+/// `lea` of data or a `call`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: usize,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// An offset into `rodata`.
+    Data(usize),
+    /// A function, which may be defined outside the program.
+    Symbol(String),
+}
+
+/// Encodes `program`. Fails on an instruction x86-64 has no encoding for,
+/// such as a move between two memory operands.
+pub fn encode(program: &Program) -> Result<Code, String> {
+    let mut rodata = vec![];
+    let mut data = HashMap::new();
+    for item in &program.data {
+        data.insert(item.label.as_str(), rodata.len());
+        rodata.extend(&item.bytes);
+    }
+
+    let mut encoder = Encoder {
+        text: vec![],
+        data,
+        labels: HashMap::new(),
+        jumps: vec![],
+        relocations: vec![],
+    };
+    let mut symbols = vec![];
+    for function in &program.functions {
+        symbols.push((function.name.clone(), encoder.text.len()));
+        for inst in &function.insts {
+            encoder
+                .inst(inst)
+                .map_err(|()| format!("cannot encode '{}'", inst.to_string().trim()))?;
+        }
+    }
+    for (offset, label) in &encoder.jumps {
+        let target = match encoder.labels.get(label.as_str()) {
+            Some(target) => *target,
+            None => return Err(format!("undefined label '{}'", label)),
+        };
+        let distance = target as i64 - (*offset as i64 + 4);
+        encoder.text[*offset..*offset + 4].copy_from_slice(&(distance as i32).to_le_bytes());
+    }
+    Ok(Code {
+        text: encoder.text,
+        rodata,
+        symbols,
+        relocations: encoder.relocations,
+    })
+}
+
+/// The number of a register in ModRM and REX encodings.
+fn number(reg: Reg) -> u8 {
+    match reg {
+        Reg::Rax => 0,
+        Reg::Rcx => 1,
+        Reg::Rbx => 3,
+        Reg::Rsp => 4,
+        Reg::Rbp => 5,
+        Reg::Rsi => 6,
+        Reg::Rdi => 7,
+        Reg::R8 => 8,
+        Reg::R9 => 9,
+        Reg::R10 => 10,
+        Reg::R11 => 11,
+        Reg::R12 => 12,
+        Reg::R13 => 13,
+        Reg::R14 => 14,
+        Reg::R15 => 15,
+    }
+}
+
+/// The condition field of `setcc` and `jcc`.
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::E => 0x4,
+        Cond::Ne => 0x5,
+        Cond::L => 0xc,
+        Cond::Le => 0xe,
+    }
+}
+
+/// An arithmetic instruction of the `add` family: its ModRM extension for
+/// immediates, and the opcodes for `r/m, reg` and `reg, r/m`.
+struct Alu {
+    ext: u8,
+    to_rm: u8,
+    to_reg: u8,
+}
+
+const ADD: Alu = Alu {
+    ext: 0,
+    to_rm: 0x01,
+    to_reg: 0x03,
+};
+const SUB: Alu = Alu {
+    ext: 5,
+    to_rm: 0x29,
+    to_reg: 0x2b,
+};
+const CMP: Alu = Alu {
+    ext: 7,
+    to_rm: 0x39,
+    to_reg: 0x3b,
+};
+
+/// REX.W, for 64-bit operands.
+const REX_W: u8 = 0x48;
+
+fn is_i8(value: i64) -> bool {
+    value as i8 as i64 == value
+}
+
+fn imm32(value: i64) -> Result<i32, ()> {
+    i32::try_from(value).map_err(|_| ())
+}
+
+struct Encoder<'a> {
+    text: Vec<u8>,
+    data: HashMap<&'a str, usize>,
+    labels: HashMap<String, usize>,
+    /// The offset of each jump displacement and the label it jumps to.
+    jumps: Vec<(usize, String)>,
+    relocations: Vec<Relocation>,
+}
+
+impl<'a> Encoder<'a> {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.text.extend(bytes);
+    }
+
+    /// Emits `opcode` with a ModRM byte for register `reg`, or an opcode
+    /// extension, and the register or memory operand `rm`. `rex` holds the
+    /// REX bits the instruction needs besides the register extensions.
+    fn modrm(&mut self, rex: u8, opcode: &[u8], reg: u8, rm: &Operand) -> Result<(), ()> {
+        let base = match rm {
+            Operand::Reg(base) | Operand::Mem { base, .. } => number(*base),
+            Operand::Rip(_) => 0,
+            Operand::Imm(_) => return Err(()),
+        };
+        let rex = rex | (reg >> 3) << 2 | base >> 3;
+        if rex != 0 {
+            self.emit(&[0x40 | rex]);
+        }
+        self.emit(opcode);
+        let reg = (reg & 7) << 3;
+        match rm {
+            Operand::Reg(_) => self.emit(&[0xc0 | reg | base & 7]),
+            Operand::Mem { disp, .. } => {
+                let mode = if is_i8(*disp as i64) { 0x40 } else { 0x80 };
+                self.emit(&[mode | reg | base & 7]);
+                // rsp and r12 as a base need a SIB byte.
+                if base & 7 == 4 {
+                    self.emit(&[0x24]);
+                }
+                if is_i8(*disp as i64) {
+                    self.emit(&[*disp as u8]);
+                } else {
+                    self.emit(&disp.to_le_bytes());
+                }
+            }
+            Operand::Rip(label) => {
+                let target = *self.data.get(label.as_str()).ok_or(())?;
+                self.emit(&[reg | 5]);
+                self.relocations.push(Relocation {
+                    offset: self.text.len(),
+                    target: Target::Data(target),
+                });
+                self.emit(&[0; 4]);
+            }
+            Operand::Imm(_) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn alu(&mut self, alu: Alu, dst: &Operand, src: &Operand) -> Result<(), ()> {
+        match (dst, src) {
+            (Operand::Reg(_) | Operand::Mem { .. }, Operand::Imm(value)) if is_i8(*value) => {
+                self.modrm(REX_W, &[0x83], alu.ext, dst)?;
+                self.emit(&[*value as u8]);
+            }
+            (Operand::Reg(_) | Operand::Mem { .. }, Operand::Imm(value)) => {
+                let value = imm32(*value)?;
+                self.modrm(REX_W, &[0x81], alu.ext, dst)?;
+                self.emit(&value.to_le_bytes());
+            }
+            (Operand::Reg(_) | Operand::Mem { .. }, Operand::Reg(src)) => {
+                self.modrm(REX_W, &[alu.to_rm], number(*src), dst)?
+            }
+            (Operand::Reg(dst), Operand::Mem { .. }) => {
+                self.modrm(REX_W, &[alu.to_reg], number(*dst), src)?
+            }
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn jump(&mut self, opcode: &[u8], label: &str) {
+        self.emit(opcode);
+        self.jumps.push((self.text.len(), label.to_string()));
+        self.emit(&[0; 4]);
+    }
+
+    fn inst(&mut self, inst: &Inst) -> Result<(), ()> {
+        match inst {
+            Inst::Label(label) => {
+                self.labels.insert(label.clone(), self.text.len());
+            }
+            Inst::Mov(Operand::Reg(dst), Operand::Imm(value)) => match imm32(*value) {
+                Ok(value) => {
+                    self.modrm(REX_W, &[0xc7], 0, &Operand::Reg(*dst))?;
+                    self.emit(&value.to_le_bytes());
+                }
+                Err(()) => {
+                    let dst = number(*dst);
+                    self.emit(&[REX_W | dst >> 3, 0xb8 | dst & 7]);
+                    self.emit(&value.to_le_bytes());
+                }
+            },
+            Inst::Mov(dst @ Operand::Mem { .. }, Operand::Imm(value)) => {
+                let value = imm32(*value)?;
+                self.modrm(REX_W, &[0xc7], 0, dst)?;
+                self.emit(&value.to_le_bytes());
+            }
+            Inst::Mov(dst @ (Operand::Reg(_) | Operand::Mem { .. }), Operand::Reg(src)) => {
+                self.modrm(REX_W, &[0x89], number(*src), dst)?
+            }
+            Inst::Mov(Operand::Reg(dst), src @ Operand::Mem { .. }) => {
+                self.modrm(REX_W, &[0x8b], number(*dst), src)?
+            }
+            Inst::Mov(..) => return Err(()),
+            Inst::Lea(dst, src @ (Operand::Mem { .. } | Operand::Rip(_))) => {
+                self.modrm(REX_W, &[0x8d], number(*dst), src)?
+            }
+            Inst::Lea(..) => return Err(()),
+            Inst::Push(Operand::Reg(src)) => {
+                let src = number(*src);
+                if src >= 8 {
+                    self.emit(&[0x41]);
+                }
+                self.emit(&[0x50 | src & 7]);
+            }
+            Inst::Push(Operand::Imm(value)) if is_i8(*value) => self.emit(&[0x6a, *value as u8]),
+            Inst::Push(Operand::Imm(value)) => {
+                let value = imm32(*value)?;
+                self.emit(&[0x68]);
+                self.emit(&value.to_le_bytes());
+            }
+            Inst::Push(src @ Operand::Mem { .. }) => self.modrm(0, &[0xff], 6, src)?,
+            Inst::Push(Operand::Rip(_)) => return Err(()),
+            Inst::Pop(dst) => {
+                let dst = number(*dst);
+                if dst >= 8 {
+                    self.emit(&[0x41]);
+                }
+                self.emit(&[0x58 | dst & 7]);
+            }
+            Inst::Add(dst, src) => self.alu(ADD, dst, src)?,
+            Inst::Sub(dst, src) => self.alu(SUB, dst, src)?,
+            Inst::Cmp(lhs, rhs) => self.alu(CMP, lhs, rhs)?,
+            Inst::Imul(dst, Operand::Imm(value)) if is_i8(*value) => {
+                self.modrm(REX_W, &[0x6b], number(*dst), &Operand::Reg(*dst))?;
+                self.emit(&[*value as u8]);
+            }
+            Inst::Imul(dst, Operand::Imm(value)) => {
+                let value = imm32(*value)?;
+                self.modrm(REX_W, &[0x69], number(*dst), &Operand::Reg(*dst))?;
+                self.emit(&value.to_le_bytes());
+            }
+            Inst::Imul(dst, src) => self.modrm(REX_W, &[0x0f, 0xaf], number(*dst), src)?,
+            Inst::Cqo => self.emit(&[REX_W, 0x99]),
+            Inst::Idiv(src) => self.modrm(REX_W, &[0xf7], 7, src)?,
+            Inst::ShlCl(dst) => self.modrm(REX_W, &[0xd3], 4, &Operand::Reg(*dst))?,
+            Inst::Set(cond, dst) => {
+                // Without a REX prefix, 4 to 7 would name ah, ch, dh and bh.
+                let rex = if (4..8).contains(&number(*dst)) {
+                    0x40
+                } else {
+                    0
+                };
+                self.modrm(
+                    rex,
+                    &[0x0f, 0x90 | cond_code(*cond)],
+                    0,
+                    &Operand::Reg(*dst),
+                )?
+            }
+            Inst::Movzb(dst, src) => {
+                self.modrm(REX_W, &[0x0f, 0xb6], number(*dst), &Operand::Reg(*src))?
+            }
+            Inst::Jmp(label) => self.jump(&[0xe9], label),
+            Inst::Jcc(cond, label) => self.jump(&[0x0f, 0x80 | cond_code(*cond)], label),
+            Inst::Call(name) => {
+                self.emit(&[0xe8]);
+                self.relocations.push(Relocation {
+                    offset: self.text.len(),
+                    target: Target::Symbol(name.clone()),
+                });
+                self.emit(&[0; 4]);
+            }
+            Inst::Ret => self.emit(&[0xc3]),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encode::{encode, Relocation, Target};
+    use crate::x86::{Cond, Data, Function, Inst, Operand, Program, Reg};
+
+    fn mem(base: Reg, disp: i32) -> Operand {
+        Operand::Mem { base, disp }
+    }
+
+    fn text(insts: Vec<Inst>) -> Vec<u8> {
+        let program = Program {
+            data: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts,
+            }],
+        };
+        encode(&program).unwrap().text
+    }
+
+    // The expected bytes are what `as` produces for the same instructions.
+    #[test]
+    fn instructions() {
+        let rax = Operand::Reg(Reg::Rax);
+        let cases = vec![
+            (Inst::Push(Operand::Reg(Reg::Rbp)), vec![0x55]),
+            (Inst::Push(Operand::Reg(Reg::R12)), vec![0x41, 0x54]),
+            (Inst::Pop(Reg::R15), vec![0x41, 0x5f]),
+            (
+                Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)),
+                vec![0x48, 0x89, 0xe5],
+            ),
+            (
+                Inst::Sub(Operand::Reg(Reg::Rsp), Operand::Imm(16)),
+                vec![0x48, 0x83, 0xec, 0x10],
+            ),
+            (
+                Inst::Sub(Operand::Reg(Reg::Rsp), Operand::Imm(2400)),
+                vec![0x48, 0x81, 0xec, 0x60, 0x09, 0x00, 0x00],
+            ),
+            (
+                Inst::Mov(mem(Reg::Rbp, -8), rax.clone()),
+                vec![0x48, 0x89, 0x45, 0xf8],
+            ),
+            (
+                Inst::Mov(rax.clone(), mem(Reg::Rbp, -2400)),
+                vec![0x48, 0x8b, 0x85, 0xa0, 0xf6, 0xff, 0xff],
+            ),
+            (
+                Inst::Mov(rax.clone(), mem(Reg::Rsp, 8)),
+                vec![0x48, 0x8b, 0x44, 0x24, 0x08],
+            ),
+            (
+                Inst::Mov(Operand::Reg(Reg::R12), Operand::Imm(-5)),
+                vec![0x49, 0xc7, 0xc4, 0xfb, 0xff, 0xff, 0xff],
+            ),
+            (
+                Inst::Mov(Operand::Reg(Reg::R9), Operand::Imm(1 << 40)),
+                vec![0x49, 0xb9, 0, 0, 0, 0, 0, 1, 0, 0],
+            ),
+            (
+                Inst::Mov(mem(Reg::R13, 0), Operand::Imm(3)),
+                vec![0x49, 0xc7, 0x45, 0x00, 0x03, 0x00, 0x00, 0x00],
+            ),
+            (
+                Inst::Add(Operand::Reg(Reg::Rbx), Operand::Reg(Reg::R14)),
+                vec![0x4c, 0x01, 0xf3],
+            ),
+            (
+                Inst::Cmp(rax.clone(), mem(Reg::Rbp, -16)),
+                vec![0x48, 0x3b, 0x45, 0xf0],
+            ),
+            (
+                Inst::Cmp(mem(Reg::Rbp, -16), Operand::Imm(0)),
+                vec![0x48, 0x83, 0x7d, 0xf0, 0x00],
+            ),
+            (
+                Inst::Imul(Reg::Rbx, Operand::Reg(Reg::R13)),
+                vec![0x49, 0x0f, 0xaf, 0xdd],
+            ),
+            (
+                Inst::Imul(Reg::Rax, Operand::Imm(1000)),
+                vec![0x48, 0x69, 0xc0, 0xe8, 0x03, 0x00, 0x00],
+            ),
+            (Inst::Cqo, vec![0x48, 0x99]),
+            (Inst::Idiv(mem(Reg::Rbp, -16)), vec![0x48, 0xf7, 0x7d, 0xf0]),
+            (Inst::ShlCl(Reg::Rax), vec![0x48, 0xd3, 0xe0]),
+            (Inst::Set(Cond::Le, Reg::R9), vec![0x41, 0x0f, 0x9e, 0xc1]),
+            (Inst::Set(Cond::L, Reg::Rsi), vec![0x40, 0x0f, 0x9c, 0xc6]),
+            (Inst::Set(Cond::E, Reg::Rax), vec![0x0f, 0x94, 0xc0]),
+            (
+                Inst::Movzb(Reg::Rax, Reg::Rax),
+                vec![0x48, 0x0f, 0xb6, 0xc0],
+            ),
+            (Inst::Ret, vec![0xc3]),
+        ];
+        for (inst, bytes) in cases {
+            let display = inst.to_string();
+            assert_eq!(text(vec![inst]), bytes, "{}", display);
+        }
+    }
+
+    #[test]
+    fn jumps() {
+        let label = ".L.main.1".to_string();
+        assert_eq!(
+            text(vec![
+                Inst::Jcc(Cond::Ne, label.clone()),
+                Inst::Label(label.clone()),
+                Inst::Jmp(label),
+            ]),
+            vec![0x0f, 0x85, 0, 0, 0, 0, 0xe9, 0xfb, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn data_references() {
+        let data = |label: &str, bytes: &[u8]| Data {
+            label: label.to_string(),
+            bytes: bytes.to_vec(),
+        };
+        let program = Program {
+            data: vec![data(".LC0", b"a\0"), data(".LC1", b"bc\0")],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts: vec![
+                    Inst::Push(Operand::Reg(Reg::Rbp)),
+                    Inst::Lea(Reg::Rax, Operand::Rip(".LC1".to_string())),
+                ],
+            }],
+        };
+        let code = encode(&program).unwrap();
+        assert_eq!(code.text, vec![0x55, 0x48, 0x8d, 0x05, 0, 0, 0, 0]);
+        assert_eq!(code.rodata, b"a\0bc\0");
+        assert_eq!(code.symbols, vec![("main".to_string(), 0)]);
+        assert_eq!(
+            code.relocations,
+            vec![Relocation {
+                offset: 4,
+                target: Target::Data(2)
+            }]
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let program = Program {
+            data: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts: vec![Inst::Call("labs".to_string()), Inst::Ret],
+            }],
+        };
+        let code = encode(&program).unwrap();
+        assert_eq!(code.text, vec![0xe8, 0, 0, 0, 0, 0xc3]);
+        assert_eq!(
+            code.relocations,
+            vec![Relocation {
+                offset: 1,
+                target: Target::Symbol("labs".to_string())
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_operands() {
+        let program = Program {
+            data: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts: vec![Inst::Mov(
+                    Operand::Mem {
+                        base: Reg::Rbp,
+                        disp: -8,
+                    },
+                    Operand::Mem {
+                        base: Reg::Rbp,
+                        disp: -16,
+                    },
+                )],
+            }],
+        };
+        assert_eq!(
+            encode(&program).unwrap_err(),
+            "cannot encode 'mov QWORD PTR [rbp - 8], QWORD PTR [rbp - 16]'"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::ast::{BinOp, Expr, Program, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+
+/// Evaluates constant subexpressions of a checked program and applies
+/// algebraic identities: `x + 0`, `x - 0`, `x * 1` and `x / 1` become `x`,
+/// and `x * 2` becomes `x << 1`. Returns the warnings found on the way,
+/// such as a division by a constant zero, which is left for run time.
+pub fn fold(program: &mut Program) -> Vec<Diagnostic> {
+    let mut folder = Folder { warnings: vec![] };
+    for stmt in &mut program.stmts {
+        match stmt {
+            Stmt::Expr { expr } | Stmt::Return { expr, .. } => folder.fold_in_place(expr),
+        }
+    }
+    folder.warnings
+}
+
+struct Folder {
+    warnings: Vec<Diagnostic>,
+}
+
+impl Folder {
+    fn fold_in_place(&mut self, expr: &mut Expr) {
+        let placeholder = Expr::Num {
+            value: 0,
+            span: expr.span(),
+        };
+        let owned = std::mem::replace(expr, placeholder);
+        *expr = self.expr(owned);
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Num { .. } | Expr::Str { .. } | Expr::Var { .. } => expr,
+            Expr::Assign { lhs, mut rhs, span } => {
+                self.fold_in_place(&mut rhs);
+                Expr::Assign { lhs, rhs, span }
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let lhs = self.expr(*lhs);
+                let rhs = self.expr(*rhs);
+                self.binary(op, lhs, rhs, span)
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+        let constant = |expr: &Expr| match expr {
+            Expr::Num { value, .. } => Some(*value),
+            _ => None,
+        };
+        match (op, constant(&lhs), constant(&rhs)) {
+            (BinOp::Div, _, Some(0)) => {
+                self.warnings.push(Diagnostic::warning(
+                    span,
+                    "division by zero [-Wdiv-by-zero]",
+                ));
+            }
+            (_, Some(a), Some(b)) => {
+                // Only `i64::MIN / -1` can fail here; it traps at run time.
+                if let Some(value) = op.eval(a, b) {
+                    return Expr::Num { value, span };
+                }
+            }
+            (BinOp::Add, _, Some(0))
+            | (BinOp::Sub, _, Some(0))
+            | (BinOp::Mul, _, Some(1))
+            | (BinOp::Div, _, Some(1)) => return lhs,
+            (BinOp::Add, Some(0), _) | (BinOp::Mul, Some(1), _) => return rhs,
+            (BinOp::Mul, _, Some(2)) => return shift_left(lhs, rhs.span(), span),
+            (BinOp::Mul, Some(2), _) => return shift_left(rhs, lhs.span(), span),
+            _ => {}
+        }
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span,
+        }
+    }
+}
+
+/// `expr << 1`, where `two` is the span of the constant it replaces.
+fn shift_left(expr: Expr, two: Span, span: Span) -> Expr {
+    Expr::Binary {
+        op: BinOp::Shl,
+        lhs: Box::new(expr),
+        rhs: Box::new(Expr::Num {
+            value: 1,
+            span: two,
+        }),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{BinOp, Expr, Program, Stmt};
+    use crate::diagnostic::{Diagnostic, Severity};
+    use crate::fold::fold;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::FileId;
+    use crate::token::Token;
+
+    fn folded(input: &str) -> (Program, Vec<Diagnostic>) {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        let mut program = Parser::new(&tokens).program().unwrap();
+        sema::analyze(&mut program).unwrap();
+        let warnings = fold(&mut program);
+        (program, warnings)
+    }
+
+    fn value(stmt: &Stmt) -> &Expr {
+        match stmt {
+            Stmt::Expr { expr } | Stmt::Return { expr, .. } => expr,
+        }
+    }
+
+    #[test]
+    fn constants() {
+        let (program, warnings) = folded("5+6*7; -(3 - 5) < 1; return (1 == 1) + (2 != 2);");
+        assert!(warnings.is_empty());
+        let values: Vec<i64> = program
+            .stmts
+            .iter()
+            .map(|stmt| match value(stmt) {
+                Expr::Num { value, .. } => *value,
+                expr => panic!("not folded: {:?}", expr),
+            })
+            .collect();
+        assert_eq!(values, vec![47, 0, 1]);
+        assert_eq!(value(&program.stmts[0]).span().len, 5);
+    }
+
+    #[test]
+    fn identities() {
+        let (program, _) =
+            folded("a = 1; a * 1 + 0; 0 + 1 * a - 0; a / 1; s = \"x\"; s + (1 - 1);");
+        for stmt in &program.stmts[1..4] {
+            assert!(matches!(value(stmt), Expr::Var { .. }), "{:?}", stmt);
+        }
+        assert!(matches!(value(&program.stmts[5]), Expr::Var { .. }));
+    }
+
+    #[test]
+    fn strength_reduction() {
+        let (program, _) = folded("a = 3; a * 2; 2 * (a + 1);");
+        for stmt in &program.stmts[1..] {
+            match value(stmt) {
+                Expr::Binary {
+                    op: BinOp::Shl,
+                    rhs,
+                    ..
+                } => assert!(matches!(**rhs, Expr::Num { value: 1, .. })),
+                expr => panic!("not reduced: {:?}", expr),
+            }
+        }
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let (program, warnings) = folded("a = 1; a / (1 - 1); 4 / 0;");
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].message, "division by zero [-Wdiv-by-zero]");
+        assert_eq!(warnings[1].span.column, 21);
+        assert!(matches!(
+            value(&program.stmts[2]),
+            Expr::Binary { op: BinOp::Div, .. }
+        ));
+    }
+}
@@ -1,126 +1,323 @@
-use crate::node::Node;
-
-pub fn gen_program(program: &Vec<Node>) -> Vec<String> {
-    let mut assembly: Vec<String> = vec!();
-    assembly.push(".intel_syntax noprefix".to_string());
-    assembly.push(".global main".to_string());
-    assembly.push("main:".to_string());
-    assembly.push("  push rbp".to_string());
-    assembly.push("  mov rbp, rsp".to_string());
-    assembly.push("  sub rsp, 208".to_string());
-    for stmt in program {
-        let (generated, returned) = &mut gen(&stmt);
-        assembly.append(generated);
-        assembly.push("  pop rax".to_string());
-        if *returned {
-            break;
+use crate::ast::BinOp;
+use crate::ir::{BlockId, Function, Inst, Module, Terminator, VReg};
+use crate::regalloc::{self, Allocation, Location};
+use crate::x86::{self, Cond, Data, Operand, Program, Reg};
+
+pub fn gen_program(module: &Module) -> Program {
+    let data = module
+        .strings
+        .iter()
+        .enumerate()
+        .map(|(i, string)| Data {
+            label: format!(".LC{}", i),
+            bytes: string.bytes().chain(std::iter::once(0)).collect(),
+        })
+        .collect();
+    let functions = module
+        .functions
+        .iter()
+        .map(|function| {
+            let mut function = function.clone();
+            function.eliminate_phis();
+            gen_function(&function)
+        })
+        .collect();
+    Program { data, functions }
+}
+
+/// Generates `function`, which must not contain phis. Virtual registers
+/// live where the register allocator puts them; spill slots and the saved
+/// callee-saved registers go below the local variables, and the frame is
+/// rounded up to keep `rsp` 16-byte aligned.
+fn gen_function(function: &Function) -> x86::Function {
+    let allocation = regalloc::allocate(function);
+    let saved = allocation.callee_saved.len();
+    // A slot's offset is the distance to its lowest byte.
+    let locals_size = function
+        .slots
+        .iter()
+        .map(|slot| slot.offset)
+        .max()
+        .unwrap_or(0);
+    let spills_start = locals_size.next_multiple_of(8);
+    let frame_size = (spills_start + 8 * (allocation.spill_slots + saved)).next_multiple_of(16);
+    let mut generator = Generator {
+        function,
+        allocation,
+        spills_start,
+        insts: vec![
+            x86::Inst::Push(Operand::Reg(Reg::Rbp)),
+            x86::Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)),
+            x86::Inst::Sub(Operand::Reg(Reg::Rsp), Operand::Imm(frame_size as i64)),
+        ],
+    };
+    for (i, reg) in generator.allocation.callee_saved.clone().iter().enumerate() {
+        let slot = generator.save_slot(i);
+        generator.emit(x86::Inst::Mov(slot, Operand::Reg(*reg)));
+    }
+    for (id, block) in function.blocks.iter().enumerate() {
+        generator.emit(x86::Inst::Label(label(function, BlockId(id))));
+        for inst in &block.insts {
+            generator.gen_inst(inst);
         }
+        generator.gen_term(&block.term);
+    }
+    x86::Function {
+        name: function.name.clone(),
+        insts: generator.insts,
     }
-    assembly.push("  mov rsp, rbp".to_string());
-    assembly.push("  pop rbp".to_string());
-    assembly.push("  ret".to_string());
-    return assembly;
 }
 
-fn gen_lval(node: &Node) -> Vec<String> {
-    let mut assembly: Vec<String> = vec!();
-    match node.offset {
-        Some(offset) => {
-            assembly.push("  mov rax, rbp".to_string());
-            assembly.push(format!("  sub rax, {}", offset));
-            assembly.push("  push rax".to_string());
-        }
-        _ => {
-            panic!("The lvalue of the assignment is not a variable")
-        }
+struct Generator<'a> {
+    function: &'a Function,
+    allocation: Allocation,
+    /// Distance below the frame pointer where the spill slots begin.
+    spills_start: usize,
+    insts: Vec<x86::Inst>,
+}
+
+/// The quadword `offset` bytes below the frame pointer.
+fn frame_slot(offset: usize) -> Operand {
+    Operand::Mem {
+        base: Reg::Rbp,
+        disp: -(offset as i32),
     }
-    return assembly;
 }
 
-fn gen(node: &Node) -> (Vec<String>, bool) {
-    let mut assembly: Vec<String> = vec!();
-    if let Some(num) = node.number {
-        assembly.push(format!("  push {}", num));
-        return (assembly, false);
-    }
-    if let Some(_) = node.offset {
-        assembly.append(&mut gen_lval(node));
-        assembly.push("  pop rax".to_string());
-        assembly.push("  mov rax, [rax]".to_string());
-        assembly.push("  push rax".to_string());
-        return (assembly, false);
-    }
-    if node.operator == Some("return".to_string()) {
-        if let Some(lhs) = &node.lhs {
-            let (generated, _) = &mut gen(&lhs);
-            assembly.append(generated);
+fn rax() -> Operand {
+    Operand::Reg(Reg::Rax)
+}
+
+impl<'a> Generator<'a> {
+    fn emit(&mut self, inst: x86::Inst) {
+        self.insts.push(inst);
+    }
+
+    fn save_slot(&self, index: usize) -> Operand {
+        frame_slot(self.spills_start + 8 * (self.allocation.spill_slots + index + 1))
+    }
+
+    fn location(&self, reg: VReg) -> Location {
+        self.allocation.locations[reg.0 as usize].expect("register without a location")
+    }
+
+    /// The operand naming where `reg` lives.
+    fn operand(&self, reg: VReg) -> Operand {
+        match self.location(reg) {
+            Location::Reg(reg) => Operand::Reg(reg),
+            Location::Spill(slot) => frame_slot(self.spills_start + 8 * (slot + 1)),
         }
-        return (assembly, true);
     }
-    if node.operator == Some("=".to_string()) {
-        if let Some(lhs) = &node.lhs {
-            assembly.append(&mut gen_lval(&lhs));
+
+    /// Moves `src` into `dst`, going through `rax` when both are in memory.
+    fn mov(&mut self, dst: VReg, src: VReg) {
+        let (dst, src) = (self.operand(dst), self.operand(src));
+        if dst == src {
+            return;
         }
-        if let Some(rhs) = &node.rhs {
-            let (generated, _) = &mut gen(&rhs);
-            assembly.append(generated);
+        if dst.is_mem() && src.is_mem() {
+            self.emit(x86::Inst::Mov(rax(), src));
+            self.emit(x86::Inst::Mov(dst, rax()));
+        } else {
+            self.emit(x86::Inst::Mov(dst, src));
         }
-        assembly.push("  pop rdi".to_string());
-        assembly.push("  pop rax".to_string());
-        assembly.push("  mov [rax], rdi".to_string());
-        assembly.push("  push rdi".to_string());
-        return (assembly, false);
-    }
-    if let Some(rhs) = &node.rhs {
-        let (generated, _) = &mut gen(&rhs);
-        assembly.append(generated);
-    }
-    if let Some(lhs) = &node.lhs {
-        let (generated, _) = &mut gen(&lhs);
-        assembly.append(generated);
-    }
-    assembly.push("  pop rax".to_string());
-    assembly.push("  pop rdi".to_string());
-
-    match &node.operator {
-        Some(op) => match op.as_ref() {
-            "+" => {
-                assembly.push("  add rax, rdi".to_string());
+    }
+
+    /// Stores `rax` to `dst`.
+    fn store_rax(&mut self, dst: VReg) {
+        let dst = self.operand(dst);
+        self.emit(x86::Inst::Mov(dst, rax()));
+    }
+
+    fn gen_term(&mut self, term: &Terminator) {
+        match term {
+            Terminator::Ret(value) => {
+                let value = self.operand(*value);
+                self.emit(x86::Inst::Mov(rax(), value));
+                for (i, reg) in self.allocation.callee_saved.clone().iter().enumerate() {
+                    let slot = self.save_slot(i);
+                    self.emit(x86::Inst::Mov(Operand::Reg(*reg), slot));
+                }
+                self.emit(x86::Inst::Mov(
+                    Operand::Reg(Reg::Rsp),
+                    Operand::Reg(Reg::Rbp),
+                ));
+                self.emit(x86::Inst::Pop(Reg::Rbp));
+                self.emit(x86::Inst::Ret);
             }
-            "-" => {
-                assembly.push("  sub rax, rdi".to_string());
+            Terminator::Jump(target) => {
+                self.emit(x86::Inst::Jmp(label(self.function, *target)));
+            }
+            Terminator::Branch { cond, then, els } => {
+                let cond = self.operand(*cond);
+                self.emit(x86::Inst::Cmp(cond, Operand::Imm(0)));
+                self.emit(x86::Inst::Jcc(Cond::E, label(self.function, *els)));
+                self.emit(x86::Inst::Jmp(label(self.function, *then)));
+            }
+        }
+    }
+
+    fn gen_inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Const { dst, value } => {
+                let dst_operand = self.operand(*dst);
+                // Only a register can take a 64-bit immediate.
+                if dst_operand.is_mem() && *value as i32 as i64 != *value {
+                    self.emit(x86::Inst::Mov(rax(), Operand::Imm(*value)));
+                    self.store_rax(*dst);
+                } else {
+                    self.emit(x86::Inst::Mov(dst_operand, Operand::Imm(*value)));
+                }
             }
-            "*" => {
-                assembly.push("  imul rax, rdi".to_string());
+            Inst::Str { dst, index } => {
+                let label = Operand::Rip(format!(".LC{}", index));
+                self.emit(x86::Inst::Lea(Reg::Rax, label));
+                self.store_rax(*dst);
             }
-            "/" => {
-                assembly.push("  cqo".to_string());
-                assembly.push("  idiv rdi".to_string());
+            Inst::Load { dst, slot } => {
+                let offset = self.function.slots[slot.0].offset;
+                self.emit(x86::Inst::Mov(rax(), frame_slot(offset)));
+                self.store_rax(*dst);
             }
-            "==" => {
-                assembly.push("  cmp rax, rdi".to_string());
-                assembly.push("  sete al".to_string());
-                assembly.push("  movzb rax, al".to_string());
+            Inst::Store { slot, src } => {
+                let offset = self.function.slots[slot.0].offset;
+                let src = self.operand(*src);
+                self.emit(x86::Inst::Mov(rax(), src));
+                self.emit(x86::Inst::Mov(frame_slot(offset), rax()));
             }
-            "!=" => {
-                assembly.push("  cmp rax, rdi".to_string());
-                assembly.push("  setne al".to_string());
-                assembly.push("  movzb rax, al".to_string());
+            Inst::Copy { dst, src } => self.mov(*dst, *src),
+            Inst::Phi { .. } => unreachable!("phis are eliminated before code generation"),
+            Inst::Binary { dst, op, lhs, rhs } => self.gen_binary(*dst, *op, *lhs, *rhs),
+        }
+    }
+
+    fn gen_binary(&mut self, dst: VReg, op: BinOp, lhs: VReg, rhs: VReg) {
+        let rhs_operand = self.operand(rhs);
+        if let BinOp::Add | BinOp::Sub | BinOp::Mul = op {
+            // Compute in place when the result has a register that does not
+            // hold the right operand.
+            let target = match self.location(dst) {
+                Location::Reg(reg) if self.location(rhs) != Location::Reg(reg) => {
+                    self.mov(dst, lhs);
+                    reg
+                }
+                _ => {
+                    let lhs = self.operand(lhs);
+                    self.emit(x86::Inst::Mov(rax(), lhs));
+                    Reg::Rax
+                }
+            };
+            self.emit(match op {
+                BinOp::Add => x86::Inst::Add(Operand::Reg(target), rhs_operand),
+                BinOp::Sub => x86::Inst::Sub(Operand::Reg(target), rhs_operand),
+                _ => x86::Inst::Imul(target, rhs_operand),
+            });
+            if target == Reg::Rax {
+                self.store_rax(dst);
             }
-            "<" => {
-                assembly.push("  cmp rax, rdi".to_string());
-                assembly.push("  setl al".to_string());
-                assembly.push("  movzb rax, al".to_string());
+            return;
+        }
+
+        let lhs = self.operand(lhs);
+        self.emit(x86::Inst::Mov(rax(), lhs));
+        let cond = match op {
+            BinOp::Div => {
+                self.emit(x86::Inst::Cqo);
+                self.emit(x86::Inst::Idiv(rhs_operand));
+                self.store_rax(dst);
+                return;
             }
-            "<=" => {
-                assembly.push("  cmp rax, rdi".to_string());
-                assembly.push("  setle al".to_string());
-                assembly.push("  movzb rax, al".to_string());
+            BinOp::Shl => {
+                self.emit(x86::Inst::Mov(Operand::Reg(Reg::Rcx), rhs_operand));
+                self.emit(x86::Inst::ShlCl(Reg::Rax));
+                self.store_rax(dst);
+                return;
             }
-            _ => {}
-        },
-        _ => {}
+            BinOp::Eq => Cond::E,
+            BinOp::Ne => Cond::Ne,
+            BinOp::Lt => Cond::L,
+            BinOp::Le => Cond::Le,
+            BinOp::Add | BinOp::Sub | BinOp::Mul => unreachable!(),
+        };
+        self.emit(x86::Inst::Cmp(rax(), rhs_operand));
+        self.emit(x86::Inst::Set(cond, Reg::Rax));
+        self.emit(x86::Inst::Movzb(Reg::Rax, Reg::Rax));
+        self.store_rax(dst);
+    }
+}
+
+fn label(function: &Function, block: BlockId) -> String {
+    format!(".L.{}.{}", function.name, block.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::gen_function;
+    use crate::ir::Slot;
+    use crate::regalloc::tests::pressure;
+    use crate::x86::{Inst, Operand, Reg};
+
+    fn reg(reg: Reg) -> Operand {
+        Operand::Reg(reg)
+    }
+
+    fn slot(offset: i32) -> Operand {
+        Operand::Mem {
+            base: Reg::Rbp,
+            disp: -offset,
+        }
+    }
+
+    const SAVED: [Reg; 5] = [Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+    #[test]
+    fn saves_callee_saved_registers() {
+        // 9 spill slots, then the 5 callee-saved registers below them.
+        let insts = gen_function(&pressure(20)).insts;
+        let mut prologue = vec![
+            Inst::Push(reg(Reg::Rbp)),
+            Inst::Mov(reg(Reg::Rbp), reg(Reg::Rsp)),
+            Inst::Sub(reg(Reg::Rsp), Operand::Imm(112)),
+        ];
+        for (i, saved) in SAVED.iter().enumerate() {
+            prologue.push(Inst::Mov(slot(80 + 8 * i as i32), reg(*saved)));
+        }
+        prologue.push(Inst::Label(".L.f.0".to_string()));
+        assert_eq!(insts[..prologue.len()], prologue[..]);
+
+        let mut epilogue = vec![];
+        for (i, saved) in SAVED.iter().enumerate() {
+            epilogue.push(Inst::Mov(reg(*saved), slot(80 + 8 * i as i32)));
+        }
+        epilogue.push(Inst::Mov(reg(Reg::Rsp), reg(Reg::Rbp)));
+        epilogue.push(Inst::Pop(Reg::Rbp));
+        epilogue.push(Inst::Ret);
+        assert_eq!(insts[insts.len() - epilogue.len()..], epilogue[..]);
+        assert!(matches!(
+            insts[insts.len() - epilogue.len() - 1],
+            Inst::Mov(Operand::Reg(Reg::Rax), _)
+        ));
+    }
+
+    #[test]
+    fn places_spills_below_locals() {
+        // Locals take 13 bytes, so the spills start at 16 and the frame of
+        // 16 + 8 * (8 spills + 5 saved) = 120 bytes is rounded up to 128.
+        let mut function = pressure(19);
+        function.slots.push(Slot {
+            name: "c".to_string(),
+            offset: 13,
+        });
+        let insts = gen_function(&function).insts;
+        assert_eq!(insts[2], Inst::Sub(reg(Reg::Rsp), Operand::Imm(128)));
+        assert_eq!(insts[3], Inst::Mov(slot(88), reg(Reg::Rbx)));
+        assert_eq!(insts[7], Inst::Mov(slot(120), reg(Reg::R15)));
+
+        // %0 has a register and %11 is the first to be spilled.
+        assert!(insts.contains(&Inst::Mov(reg(Reg::Rsi), Operand::Imm(0))));
+        assert!(insts.contains(&Inst::Mov(slot(24), Operand::Imm(11))));
+        assert!(insts
+            .iter()
+            .any(|inst| matches!(inst, Inst::Add(Operand::Reg(_), rhs) if *rhs == slot(24))));
     }
-    assembly.push("  push rax".to_string());
-    return (assembly, false);
 }
@@ -0,0 +1,189 @@
+//! A tree-walking interpreter over the checked AST. It is the reference
+//! semantics the code generator is tested against, so values behave as in
+//! the generated code: integers are 64-bit and wrap, and pointers are
+//! addresses. String literals are laid out one after another in a data
+//! segment in the order lowering emits them, as `.rodata` is, and locals
+//! live in a stack frame at the offsets `layout` assigned.
+
+use crate::ast::{BinOp, Expr, Program, Stmt};
+use crate::diagnostic::Diagnostic;
+use std::collections::HashMap;
+
+/// The address of the first string literal.
+const DATA_BASE: i64 = 0x1000;
+/// The frame pointer of `main`; its locals lie below it.
+const STACK_TOP: i64 = 0x7fff_0000;
+
+/// The simulated address space: a read-only data segment holding the
+/// string literals, followed much higher up by the stack.
+struct Memory {
+    data: Vec<u8>,
+    /// The bytes from `STACK_TOP - stack.len()` up to `STACK_TOP`.
+    stack: Vec<u8>,
+}
+
+impl Memory {
+    /// The stack bytes at `address`, which must lie in the frame.
+    fn stack_bytes(&mut self, address: i64, size: usize) -> &mut [u8] {
+        let start = (address - (STACK_TOP - self.stack.len() as i64)) as usize;
+        &mut self.stack[start..start + size]
+    }
+
+    /// Loads a little-endian, sign-extended value of `size` bytes.
+    fn load(&mut self, address: i64, size: usize) -> i64 {
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(self.stack_bytes(address, size));
+        let shift = 64 - 8 * size as u32;
+        (i64::from_le_bytes(bytes) << shift) >> shift
+    }
+
+    fn store(&mut self, address: i64, size: usize, value: i64) {
+        self.stack_bytes(address, size)
+            .copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+}
+
+/// Runs a checked program whose locals have been laid out, and returns the
+/// value `main` returns. Division by zero and overflowing division, which
+/// trap in the generated code, are reported as errors.
+pub fn run(program: &Program) -> Result<i64, Diagnostic> {
+    let frame_size = program
+        .locals
+        .iter()
+        .map(|lvar| lvar.offset)
+        .max()
+        .unwrap_or(0)
+        .next_multiple_of(16);
+    let mut interpreter = Interpreter {
+        program,
+        memory: Memory {
+            data: vec![],
+            stack: vec![0; frame_size],
+        },
+        literals: HashMap::new(),
+    };
+    for stmt in &program.stmts {
+        match stmt {
+            Stmt::Expr { expr } | Stmt::Return { expr, .. } => interpreter.place_literals(expr),
+        }
+    }
+
+    let mut last = 0;
+    for stmt in &program.stmts {
+        match stmt {
+            Stmt::Expr { expr } => last = interpreter.eval(expr)?,
+            Stmt::Return { expr, .. } => return interpreter.eval(expr),
+        }
+    }
+    Ok(last)
+}
+
+struct Interpreter<'a> {
+    program: &'a Program,
+    memory: Memory,
+    /// The address of each string literal, by the offset of its span.
+    literals: HashMap<usize, i64>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Copies the string literals of `expr` into the data segment, visiting
+    /// them in the order lowering does.
+    fn place_literals(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Num { .. } | Expr::Var { .. } => {}
+            Expr::Str { value, span } => {
+                let address = DATA_BASE + self.memory.data.len() as i64;
+                self.memory.data.extend(value.bytes());
+                self.memory.data.push(0);
+                self.literals.insert(span.offset, address);
+            }
+            Expr::Assign { rhs, .. } => self.place_literals(rhs),
+            Expr::Binary { lhs, rhs, .. } => {
+                self.place_literals(lhs);
+                self.place_literals(rhs);
+            }
+        }
+    }
+
+    /// The address and size of local variable `id`.
+    fn local(&self, id: &Option<usize>) -> (i64, usize) {
+        let lvar = &self.program.locals[id.expect("variables are resolved by semantic analysis")];
+        (STACK_TOP - lvar.offset as i64, lvar.ty.size())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, Diagnostic> {
+        match expr {
+            Expr::Num { value, .. } => Ok(*value),
+            Expr::Str { span, .. } => Ok(self.literals[&span.offset]),
+            Expr::Var { id, .. } => {
+                let (address, size) = self.local(id);
+                Ok(self.memory.load(address, size))
+            }
+            Expr::Assign { lhs, rhs, .. } => {
+                let value = self.eval(rhs)?;
+                match &**lhs {
+                    Expr::Var { id, .. } => {
+                        let (address, size) = self.local(id);
+                        self.memory.store(address, size, value);
+                    }
+                    _ => unreachable!("lvalues are checked by semantic analysis"),
+                }
+                Ok(value)
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                match op.eval(lhs, rhs) {
+                    Some(value) => Ok(value),
+                    None if *op == BinOp::Div && rhs == 0 => {
+                        Err(Diagnostic::error(*span, "division by zero"))
+                    }
+                    None => Err(Diagnostic::error(*span, "division overflow")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{run, Options};
+
+    fn result(input: &str) -> i64 {
+        run(input, &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(result("5+6*7;"), 47);
+        assert_eq!(result("-(3 - 5) * 4 / 3;"), 2);
+        assert_eq!(result("(1 < 2) + (2 <= 2) + (2 == 3) + (2 != 3);"), 3);
+        assert_eq!(result("4294967296 * 4294967296;"), 0);
+        assert_eq!(result(""), 0);
+    }
+
+    #[test]
+    fn locals_and_return() {
+        assert_eq!(result("a = 3; b = a * 2; a + b;"), 9);
+        assert_eq!(result("a = b = 4; return a + b; 1;"), 8);
+        assert_eq!(result("a = 1; return a; a = 2;"), 1);
+    }
+
+    #[test]
+    fn pointers() {
+        assert_eq!(result("s = \"abc\"; t = s + 3; t - s;"), 3);
+        assert_eq!(result("s = \"abc\"; \"abc\" == s;"), 0);
+        // Literals are laid out back to back, NUL included.
+        assert_eq!(result("s = \"ab\"; t = \"c\"; t - s;"), 3);
+        assert_eq!(result("s = \"ab\"; t = s + 1; (s < t) + (t <= s);"), 1);
+    }
+
+    #[test]
+    fn division_traps() {
+        let errors = run("a = 0; 1 / a;", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].message, "division by zero");
+        assert_eq!(errors[0].span.column, 8);
+        let errors = run("a = -9223372036854775807 - 1; a / -1;", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].message, "division overflow");
+    }
+}
@@ -0,0 +1,631 @@
+use crate::ast::BinOp;
+use crate::span::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A virtual register. Every register is assigned by exactly one
+/// instruction until phis are eliminated before code generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VReg(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+/// Index into `Function::slots`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: VReg,
+        value: i64,
+    },
+    /// Loads the address of `Module::strings[index]`.
+    Str {
+        dst: VReg,
+        index: usize,
+    },
+    Binary {
+        dst: VReg,
+        op: BinOp,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    Load {
+        dst: VReg,
+        slot: SlotId,
+    },
+    Store {
+        slot: SlotId,
+        src: VReg,
+    },
+    /// Selects the value that flowed in from the predecessor actually taken.
+    /// Phis only appear at the start of a block.
+    Phi {
+        dst: VReg,
+        args: Vec<(BlockId, VReg)>,
+    },
+    Copy {
+        dst: VReg,
+        src: VReg,
+    },
+}
+
+impl Inst {
+    pub fn dst(&self) -> Option<VReg> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Str { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Phi { dst, .. }
+            | Inst::Copy { dst, .. } => Some(*dst),
+            Inst::Store { .. } => None,
+        }
+    }
+
+    pub fn uses(&self) -> Vec<VReg> {
+        match self {
+            Inst::Const { .. } | Inst::Str { .. } | Inst::Load { .. } => vec![],
+            Inst::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Inst::Store { src, .. } | Inst::Copy { src, .. } => vec![*src],
+            Inst::Phi { args, .. } => args.iter().map(|(_, value)| *value).collect(),
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut VReg> {
+        match self {
+            Inst::Const { .. } | Inst::Str { .. } | Inst::Load { .. } => vec![],
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::Store { src, .. } | Inst::Copy { src, .. } => vec![src],
+            Inst::Phi { args, .. } => args.iter_mut().map(|(_, value)| value).collect(),
+        }
+    }
+
+    /// Whether the instruction does more than define its destination.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, Inst::Store { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Ret(VReg),
+    Jump(BlockId),
+    Branch {
+        cond: VReg,
+        then: BlockId,
+        els: BlockId,
+    },
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Ret(_) => vec![],
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch { then, els, .. } => vec![*then, *els],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Ret(_) => vec![],
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { then, els, .. } => vec![then, els],
+        }
+    }
+
+    pub fn uses(&self) -> Vec<VReg> {
+        match self {
+            Terminator::Ret(value) | Terminator::Branch { cond: value, .. } => vec![*value],
+            Terminator::Jump(_) => vec![],
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut VReg> {
+        match self {
+            Terminator::Ret(value) | Terminator::Branch { cond: value, .. } => vec![value],
+            Terminator::Jump(_) => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+    /// The first statement lowered into the block, for diagnostics.
+    pub span: Option<Span>,
+}
+
+/// A stack slot holding a local variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub name: String,
+    /// Distance below the frame pointer.
+    pub offset: usize,
+}
+
+/// A function as a control-flow graph. `blocks[0]` is the entry block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub blocks: Vec<Block>,
+    pub slots: Vec<Slot>,
+    pub vreg_count: u32,
+}
+
+impl Function {
+    pub fn new_vreg(&mut self) -> VReg {
+        self.vreg_count += 1;
+        VReg(self.vreg_count - 1)
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![vec![]; self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for succ in block.term.successors() {
+                if !preds[succ.0].contains(&BlockId(id)) {
+                    preds[succ.0].push(BlockId(id));
+                }
+            }
+        }
+        preds
+    }
+
+    /// Blocks reachable from the entry, in reverse postorder.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        // Each entry is a block and the number of its successors visited so far.
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            let succs = self.blocks[block.0].term.successors();
+            if next < succs.len() {
+                stack.push((block, next + 1));
+                let succ = succs[next];
+                if !visited[succ.0] {
+                    visited[succ.0] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Rewrites every use of a register in `map` to its replacement,
+    /// following chains of replacements.
+    pub fn replace_uses(&mut self, map: &HashMap<VReg, VReg>) {
+        if map.is_empty() {
+            return;
+        }
+        let resolve = |reg: &mut VReg| {
+            while let Some(replacement) = map.get(reg) {
+                *reg = *replacement;
+            }
+        };
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                inst.uses_mut().into_iter().for_each(resolve);
+            }
+            block.term.uses_mut().into_iter().for_each(resolve);
+        }
+    }
+
+    /// Deletes the blocks for which `keep` is false and renumbers the rest.
+    /// Phi arguments coming from deleted blocks are dropped; no kept block
+    /// may jump to a deleted one.
+    pub fn retain_blocks(&mut self, keep: &[bool]) {
+        let mut renumbered = vec![None; self.blocks.len()];
+        let mut next = 0;
+        for (id, kept) in keep.iter().enumerate() {
+            if *kept {
+                renumbered[id] = Some(BlockId(next));
+                next += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        for (id, mut block) in blocks.into_iter().enumerate() {
+            if !keep[id] {
+                continue;
+            }
+            for inst in &mut block.insts {
+                if let Inst::Phi { args, .. } = inst {
+                    args.retain(|(pred, _)| keep[pred.0]);
+                    for (pred, _) in args.iter_mut() {
+                        *pred = renumbered[pred.0].unwrap();
+                    }
+                }
+            }
+            for succ in block.term.successors_mut() {
+                *succ = renumbered[succ.0].expect("jump to a deleted block");
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    /// Whether each block can be reached from the entry.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        for block in self.reverse_postorder() {
+            reachable[block.0] = true;
+        }
+        reachable
+    }
+
+    /// Deletes the blocks that cannot be reached from the entry. Returns
+    /// whether any block was deleted.
+    pub fn remove_unreachable_blocks(&mut self) -> bool {
+        let reachable = self.reachable();
+        if reachable.iter().all(|reachable| *reachable) {
+            return false;
+        }
+        self.retain_blocks(&reachable);
+        true
+    }
+
+    /// Replaces phis with copies so that a backend only sees ordinary
+    /// instructions. Each phi gets a fresh register that every predecessor
+    /// assigns just before its terminator, and the phi itself becomes a copy
+    /// from it; this stays correct for critical edges and for phis that read
+    /// each other.
+    pub fn eliminate_phis(&mut self) {
+        for id in 0..self.blocks.len() {
+            let mut copies: Vec<(BlockId, Inst)> = vec![];
+            for inst in self.blocks[id].insts.iter_mut() {
+                if let Inst::Phi { dst, args } = inst {
+                    let temp = VReg(self.vreg_count);
+                    self.vreg_count += 1;
+                    for (pred, value) in args.iter() {
+                        copies.push((
+                            *pred,
+                            Inst::Copy {
+                                dst: temp,
+                                src: *value,
+                            },
+                        ));
+                    }
+                    *inst = Inst::Copy {
+                        dst: *dst,
+                        src: temp,
+                    };
+                }
+            }
+            for (pred, copy) in copies {
+                self.blocks[pred.0].insts.push(copy);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    /// String literals, without their terminating NUL.
+    pub strings: Vec<String>,
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl Function {
+    /// `inst` as written in dumps, where slots are named after their
+    /// variables.
+    fn inst_text(&self, inst: &Inst) -> String {
+        match inst {
+            Inst::Const { dst, value } => format!("{} = const {}", dst, value),
+            Inst::Str { dst, index } => format!("{} = str .LC{}", dst, index),
+            Inst::Binary { dst, op, lhs, rhs } => {
+                format!("{} = {} {}, {}", dst, op_name(*op), lhs, rhs)
+            }
+            Inst::Load { dst, slot } => format!("{} = load ${}", dst, self.slots[slot.0].name),
+            Inst::Store { slot, src } => format!("store ${}, {}", self.slots[slot.0].name, src),
+            Inst::Phi { dst, args } => {
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|(block, value)| format!("[{}: {}]", block, value))
+                    .collect();
+                format!("{} = phi {}", dst, args.join(", "))
+            }
+            Inst::Copy { dst, src } => format!("{} = copy {}", dst, src),
+        }
+    }
+}
+
+fn op_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Eq => "eq",
+        BinOp::Ne => "ne",
+        BinOp::Lt => "lt",
+        BinOp::Le => "le",
+        BinOp::Shl => "shl",
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Ret(value) => write!(f, "ret {}", value),
+            Terminator::Jump(target) => write!(f, "jmp {}", target),
+            Terminator::Branch { cond, then, els } => write!(f, "br {}, {}, {}", cond, then, els),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "function {} {{", self.name)?;
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(id))?;
+            for inst in &block.insts {
+                writeln!(f, "  {}", self.inst_text(inst))?;
+            }
+            writeln!(f, "  {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, string) in self.strings.iter().enumerate() {
+            writeln!(f, ".LC{} = {:?}", index, string)?;
+        }
+        for (index, function) in self.functions.iter().enumerate() {
+            // A blank line separates the functions from what comes before.
+            if index > 0 || !self.strings.is_empty() {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl Module {
+    /// The control-flow graph of every function in Graphviz format, one
+    /// cluster per function and one node per block.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+        for function in &self.functions {
+            let node = |block: BlockId| format!("\"{}.{}\"", function.name, block);
+            out += &format!("  subgraph \"cluster_{}\" {{\n", function.name);
+            out += &format!("    label={};\n", dot_string(&function.name));
+            for (id, block) in function.blocks.iter().enumerate() {
+                // `\l` ends a left-aligned line.
+                let mut label = format!("{}:\\l", BlockId(id));
+                for inst in &block.insts {
+                    label += &format!("{}\\l", function.inst_text(inst));
+                }
+                label += &format!("{}\\l", block.term);
+                out += &format!(
+                    "    {} [label=\"{}\"];\n",
+                    node(BlockId(id)),
+                    dot_escape(&label)
+                );
+            }
+            for (id, block) in function.blocks.iter().enumerate() {
+                let from = node(BlockId(id));
+                match &block.term {
+                    Terminator::Ret(_) => {}
+                    Terminator::Jump(target) => {
+                        out += &format!("    {} -> {};\n", from, node(*target));
+                    }
+                    Terminator::Branch { then, els, .. } => {
+                        out += &format!("    {} -> {} [label=\"true\"];\n", from, node(*then));
+                        out += &format!("    {} -> {} [label=\"false\"];\n", from, node(*els));
+                    }
+                }
+            }
+            out += "  }\n";
+        }
+        out += "}\n";
+        out
+    }
+}
+
+/// Escapes quotes in a Graphviz label, keeping `\l` line breaks intact.
+fn dot_escape(label: &str) -> String {
+    label.replace('"', "\\\"")
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", dot_escape(value))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::ast::BinOp;
+    use crate::ir::{Block, BlockId, Function, Inst, Module, Terminator, VReg};
+
+    /// bb0 branches to bb1 or bb2, which both jump to bb3 where a phi merges
+    /// the value each of them defined. Shared by the tests of the passes.
+    pub fn diamond() -> Function {
+        Function {
+            name: "f".to_string(),
+            blocks: vec![
+                Block {
+                    insts: vec![Inst::Const {
+                        dst: VReg(0),
+                        value: 1,
+                    }],
+                    term: Terminator::Branch {
+                        cond: VReg(0),
+                        then: BlockId(1),
+                        els: BlockId(2),
+                    },
+                    span: None,
+                },
+                Block {
+                    insts: vec![Inst::Const {
+                        dst: VReg(1),
+                        value: 10,
+                    }],
+                    term: Terminator::Jump(BlockId(3)),
+                    span: None,
+                },
+                Block {
+                    insts: vec![Inst::Const {
+                        dst: VReg(2),
+                        value: 20,
+                    }],
+                    term: Terminator::Jump(BlockId(3)),
+                    span: None,
+                },
+                Block {
+                    insts: vec![
+                        Inst::Phi {
+                            dst: VReg(3),
+                            args: vec![(BlockId(1), VReg(1)), (BlockId(2), VReg(2))],
+                        },
+                        Inst::Binary {
+                            dst: VReg(4),
+                            op: BinOp::Add,
+                            lhs: VReg(3),
+                            rhs: VReg(0),
+                        },
+                    ],
+                    term: Terminator::Ret(VReg(4)),
+                    span: None,
+                },
+            ],
+            slots: vec![],
+            vreg_count: 5,
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            diamond().to_string(),
+            "function f {\n\
+             bb0:\n  %0 = const 1\n  br %0, bb1, bb2\n\
+             bb1:\n  %1 = const 10\n  jmp bb3\n\
+             bb2:\n  %2 = const 20\n  jmp bb3\n\
+             bb3:\n  %3 = phi [bb1: %1], [bb2: %2]\n  %4 = add %3, %0\n  ret %4\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn predecessors() {
+        let preds = diamond().predecessors();
+        assert_eq!(preds[0], vec![]);
+        assert_eq!(preds[3], vec![BlockId(1), BlockId(2)]);
+    }
+
+    #[test]
+    fn reverse_postorder() {
+        let mut function = diamond();
+        assert_eq!(
+            function.reverse_postorder(),
+            vec![BlockId(0), BlockId(2), BlockId(1), BlockId(3)]
+        );
+        function.blocks[0].term = Terminator::Jump(BlockId(2));
+        assert_eq!(
+            function.reverse_postorder(),
+            vec![BlockId(0), BlockId(2), BlockId(3)]
+        );
+    }
+
+    #[test]
+    fn remove_unreachable_blocks() {
+        let mut function = diamond();
+        assert!(!function.remove_unreachable_blocks());
+        function.blocks[0].term = Terminator::Jump(BlockId(2));
+        assert!(function.remove_unreachable_blocks());
+        assert_eq!(function.blocks.len(), 3);
+        assert_eq!(function.blocks[1].term, Terminator::Jump(BlockId(2)));
+        assert_eq!(
+            function.blocks[2].insts[0],
+            Inst::Phi {
+                dst: VReg(3),
+                args: vec![(BlockId(1), VReg(2))]
+            }
+        );
+    }
+
+    #[test]
+    fn eliminate_phis() {
+        let mut function = diamond();
+        function.eliminate_phis();
+        assert_eq!(
+            function.blocks[1].insts[1],
+            Inst::Copy {
+                dst: VReg(5),
+                src: VReg(1)
+            }
+        );
+        assert_eq!(
+            function.blocks[2].insts[1],
+            Inst::Copy {
+                dst: VReg(5),
+                src: VReg(2)
+            }
+        );
+        assert_eq!(
+            function.blocks[3].insts[0],
+            Inst::Copy {
+                dst: VReg(3),
+                src: VReg(5)
+            }
+        );
+        assert_eq!(function.vreg_count, 6);
+    }
+
+    #[test]
+    fn display_module() {
+        let module = Module {
+            functions: vec![diamond(), diamond()],
+            strings: vec!["a\n".to_string()],
+        };
+        let text = module.to_string();
+        assert!(text.starts_with(".LC0 = \"a\\n\"\n\nfunction f {\n"));
+        assert!(text.contains("}\n\nfunction f {\n"));
+    }
+
+    #[test]
+    fn to_dot() {
+        let module = Module {
+            functions: vec![diamond()],
+            strings: vec![],
+        };
+        assert_eq!(
+            module.to_dot(),
+            "digraph cfg {\n\
+             \x20 node [shape=box, fontname=\"monospace\"];\n\
+             \x20 subgraph \"cluster_f\" {\n\
+             \x20   label=\"f\";\n\
+             \x20   \"f.bb0\" [label=\"bb0:\\l%0 = const 1\\lbr %0, bb1, bb2\\l\"];\n\
+             \x20   \"f.bb1\" [label=\"bb1:\\l%1 = const 10\\ljmp bb3\\l\"];\n\
+             \x20   \"f.bb2\" [label=\"bb2:\\l%2 = const 20\\ljmp bb3\\l\"];\n\
+             \x20   \"f.bb3\" [label=\"bb3:\\l%3 = phi [bb1: %1], [bb2: %2]\\l\
+             %4 = add %3, %0\\lret %4\\l\"];\n\
+             \x20   \"f.bb0\" -> \"f.bb1\" [label=\"true\"];\n\
+             \x20   \"f.bb0\" -> \"f.bb2\" [label=\"false\"];\n\
+             \x20   \"f.bb1\" -> \"f.bb3\";\n\
+             \x20   \"f.bb2\" -> \"f.bb3\";\n\
+             \x20 }\n\
+             }\n"
+        );
+    }
+}
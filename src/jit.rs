@@ -0,0 +1,354 @@
+//! Runs compiled code in-process. The machine code is mapped into memory
+//! followed by the read-only data, the references to the data and the
+//! calls are resolved, and the pages are made executable and read-only
+//! before `main` is called, all without the system assembler or linker.
+//!
+//! Functions the program calls but does not define are looked up in libc
+//! with `dlsym`. They may be mapped too far away for a 32-bit call, so each
+//! call goes through a stub after the code that jumps to the full address.
+
+use crate::encode::{self, Code, Target};
+use crate::x86::Program;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const PROT_EXEC: c_int = 4;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const PAGE_SIZE: usize = 4096;
+const RTLD_NOW: c_int = 2;
+
+/// The size of a stub, `jmp [rip]` followed by the address, rounded up.
+const STUB_SIZE: usize = 16;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+}
+
+/// The address of the libc function `name`, if there is one.
+fn libc_symbol(name: &str) -> Option<usize> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let handle = dlopen(b"libc.so.6\0".as_ptr() as *const c_char, RTLD_NOW);
+        if handle.is_null() {
+            return None;
+        }
+        let address = dlsym(handle, name.as_ptr()) as usize;
+        // libc stays loaded, since the process itself links it.
+        dlclose(handle);
+        match address {
+            0 => None,
+            address => Some(address),
+        }
+    }
+}
+
+/// A program loaded into executable memory, unmapped when this is dropped.
+pub struct Jit {
+    memory: *mut u8,
+    len: usize,
+    main: usize,
+}
+
+impl Jit {
+    /// Encodes `program` and loads it, with calls to functions it does not
+    /// define going to libc. Fails if it has no `main`, calls a function
+    /// that is not found, cannot be encoded, or cannot be mapped.
+    pub fn load(program: &Program) -> Result<Jit, String> {
+        Jit::load_with(program, libc_symbol)
+    }
+
+    /// Like `load`, but `resolve` gives the address of each function the
+    /// program calls but does not define, or `None` if there is no such
+    /// function.
+    pub fn load_with(
+        program: &Program,
+        resolve: impl Fn(&str) -> Option<usize>,
+    ) -> Result<Jit, String> {
+        let code = encode::encode(program)?;
+        let main = match code.symbols.iter().find(|(name, _)| name == "main") {
+            Some((_, offset)) => *offset,
+            None => return Err("undefined reference to 'main'".to_string()),
+        };
+        let mut targets: HashMap<&str, usize> = code
+            .symbols
+            .iter()
+            .map(|(name, offset)| (name.as_str(), *offset))
+            .collect();
+        let mut stubs = vec![];
+        for relocation in &code.relocations {
+            let name = match &relocation.target {
+                Target::Symbol(name) if !targets.contains_key(name.as_str()) => name,
+                _ => continue,
+            };
+            let address = match resolve(name) {
+                Some(address) => address,
+                None => return Err(format!("undefined reference to '{}'", name)),
+            };
+            targets.insert(name, stubs_start(&code) + STUB_SIZE * stubs.len());
+            stubs.push(address);
+        }
+        let text_len = (stubs_start(&code) + STUB_SIZE * stubs.len()).next_multiple_of(PAGE_SIZE);
+        let len = text_len + code.rodata.len().next_multiple_of(PAGE_SIZE);
+        let memory = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        // mmap returns MAP_FAILED, not null, on failure.
+        if memory as isize == -1 {
+            return Err(format!("cannot map memory: {}", io::Error::last_os_error()));
+        }
+        let jit = Jit {
+            memory: memory as *mut u8,
+            len,
+            main,
+        };
+        let bytes = unsafe { slice::from_raw_parts_mut(jit.memory, len) };
+        place(&code, &targets, &stubs, bytes, text_len);
+        jit.protect(0, text_len, PROT_READ | PROT_EXEC)?;
+        jit.protect(text_len, len - text_len, PROT_READ)?;
+        Ok(jit)
+    }
+
+    fn protect(&self, start: usize, len: usize, prot: c_int) -> Result<(), String> {
+        if len == 0 {
+            return Ok(());
+        }
+        match unsafe { mprotect(self.memory.add(start) as *mut c_void, len, prot) } {
+            0 => Ok(()),
+            _ => Err(format!(
+                "cannot protect memory: {}",
+                io::Error::last_os_error()
+            )),
+        }
+    }
+
+    /// Calls `main` and returns its result. A trap in the program, such as
+    /// a division by zero, kills the process as it would the executable.
+    ///
+    /// # Safety
+    ///
+    /// The loaded code runs as part of this process, so it must be sound
+    /// to call: `main` must follow the System V calling convention for a
+    /// function taking nothing and returning an `i64`, and every function
+    /// it calls must be called with the arguments it expects. Programs from
+    /// `compile` meet this.
+    pub unsafe fn run_main(&self) -> i64 {
+        let main: extern "C" fn() -> i64 = std::mem::transmute(self.memory.add(self.main));
+        main()
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.memory as *mut c_void, self.len);
+        }
+    }
+}
+
+/// Where the stubs start in memory, after the code.
+fn stubs_start(code: &Code) -> usize {
+    code.text.len().next_multiple_of(STUB_SIZE)
+}
+
+/// Copies the code to the start of `memory`, followed by a stub jumping to
+/// each address of `stubs`, and the data to `rodata_start`. Then points
+/// the references to the data at it and the calls at `targets`, the
+/// offsets of the functions and stubs.
+fn place(
+    code: &Code,
+    targets: &HashMap<&str, usize>,
+    stubs: &[usize],
+    memory: &mut [u8],
+    rodata_start: usize,
+) {
+    memory[..code.text.len()].copy_from_slice(&code.text);
+    for (i, address) in stubs.iter().enumerate() {
+        let stub = stubs_start(code) + STUB_SIZE * i;
+        memory[stub..stub + 6].copy_from_slice(&[0xff, 0x25, 0, 0, 0, 0]);
+        memory[stub + 6..stub + 14].copy_from_slice(&(*address as u64).to_le_bytes());
+    }
+    memory[rodata_start..rodata_start + code.rodata.len()].copy_from_slice(&code.rodata);
+    for relocation in &code.relocations {
+        let target = match &relocation.target {
+            Target::Data(offset) => rodata_start + offset,
+            Target::Symbol(name) => targets[name.as_str()],
+        };
+        let distance = target as i64 - (relocation.offset as i64 + 4);
+        memory[relocation.offset..relocation.offset + 4]
+            .copy_from_slice(&(distance as i32).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jit::Jit;
+    use crate::opt::OptLevel;
+    use crate::x86::{Function, Inst, Operand, Program, Reg};
+    use crate::{compile, Options};
+
+    fn result(input: &str, level: OptLevel) -> i64 {
+        let options = Options {
+            level,
+            ..Options::default()
+        };
+        let output = compile(input, &options).unwrap();
+        unsafe { Jit::load(&output.assembly).unwrap().run_main() }
+    }
+
+    #[test]
+    fn runs_programs() {
+        let cases = [
+            (47, "5+6*7;"),
+            (3, "a = 7; b = a / 2; a - b - 1;"),
+            (1, "(1 < 2) + (2 <= 1) + (3 == 4);"),
+            (5, "return 5; return 10;"),
+            (0, "4294967296 * 4294967296;"),
+            (2, "s = \"ab\"; t = \"c\"; t - s - 1;"),
+        ];
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            for (expected, input) in &cases {
+                assert_eq!(
+                    result(input, level),
+                    *expected,
+                    "{} with {:?}",
+                    input,
+                    level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_string_literals() {
+        // A pointer into rodata can be compared, but the language has no
+        // way to read through it, so check that it points at the data.
+        let output = compile("s = \"hi\"; s;", &Options::default()).unwrap();
+        let jit = Jit::load(&output.assembly).unwrap();
+        let address = unsafe { jit.run_main() };
+        let bytes = unsafe { std::slice::from_raw_parts(address as *const u8, 3) };
+        assert_eq!(bytes, b"hi\0");
+    }
+
+    fn function(name: &str, insts: Vec<Inst>) -> Function {
+        Function {
+            name: name.to_string(),
+            insts,
+        }
+    }
+
+    /// A `main` that returns what `callee` returns for `arg`, keeping the
+    /// stack aligned for the call.
+    fn calling(callee: &str, arg: i64) -> Function {
+        function(
+            "main",
+            vec![
+                Inst::Push(Operand::Reg(Reg::Rbp)),
+                Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)),
+                Inst::Mov(Operand::Reg(Reg::Rdi), Operand::Imm(arg)),
+                Inst::Call(callee.to_string()),
+                Inst::Pop(Reg::Rbp),
+                Inst::Ret,
+            ],
+        )
+    }
+
+    #[test]
+    fn calls_functions() {
+        let seven = function(
+            "seven",
+            vec![
+                Inst::Mov(Operand::Reg(Reg::Rax), Operand::Imm(7)),
+                Inst::Ret,
+            ],
+        );
+        let program = Program {
+            data: vec![],
+            functions: vec![seven, calling("seven", 0)],
+        };
+        assert_eq!(unsafe { Jit::load(&program).unwrap().run_main() }, 7);
+    }
+
+    #[test]
+    fn calls_compiled_functions() {
+        // The language cannot call functions yet, so call a compiled one
+        // from a hand-built main, and pass its result to libc.
+        let output = compile("a = 0 - 9; return a;", &Options::default()).unwrap();
+        let mut program = output.assembly;
+        program.functions[0].name = "value".to_string();
+        program.functions.push(function(
+            "main",
+            vec![
+                Inst::Push(Operand::Reg(Reg::Rbp)),
+                Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)),
+                Inst::Call("value".to_string()),
+                Inst::Mov(Operand::Reg(Reg::Rdi), Operand::Reg(Reg::Rax)),
+                Inst::Call("labs".to_string()),
+                Inst::Pop(Reg::Rbp),
+                Inst::Ret,
+            ],
+        ));
+        assert_eq!(unsafe { Jit::load(&program).unwrap().run_main() }, 9);
+    }
+
+    #[test]
+    fn calls_libc() {
+        let program = Program {
+            data: vec![],
+            functions: vec![calling("labs", -5)],
+        };
+        assert_eq!(unsafe { Jit::load(&program).unwrap().run_main() }, 5);
+    }
+
+    extern "C" fn double(value: i64) -> i64 {
+        value * 2
+    }
+
+    #[test]
+    fn resolves_symbols_with_hook() {
+        let program = Program {
+            data: vec![],
+            functions: vec![calling("double", 21)],
+        };
+        let resolve = |name: &str| match name {
+            "double" => Some(double as *const () as usize),
+            _ => None,
+        };
+        let jit = Jit::load_with(&program, resolve).unwrap();
+        assert_eq!(unsafe { jit.run_main() }, 42);
+
+        let program = Program {
+            data: vec![],
+            functions: vec![calling("no_such_function", 0)],
+        };
+        match Jit::load(&program) {
+            Err(message) => assert_eq!(message, "undefined reference to 'no_such_function'"),
+            Ok(_) => panic!("loaded a call to an undefined function"),
+        }
+    }
+}
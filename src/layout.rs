@@ -0,0 +1,56 @@
+use crate::ast::Program;
+
+/// Assigns every local variable a stack slot below the frame pointer, sized
+/// and aligned for its type. Runs after semantic analysis, once the set of
+/// locals and their types are known.
+pub fn assign_offsets(program: &mut Program) {
+    let mut offset = 0;
+    for lvar in &mut program.locals {
+        offset = (offset + lvar.ty.size()).next_multiple_of(lvar.ty.align());
+        lvar.offset = offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{LVar, Program};
+    use crate::layout::assign_offsets;
+    use crate::parser::Parser;
+    use crate::sema::analyze;
+    use crate::span::{FileId, Span};
+    use crate::token::Token;
+    use crate::types::Type;
+
+    #[test]
+    fn offsets() {
+        let tokens = Token::parse(FileId(0), "a = 1; b = \"x\"; a = b - b;").unwrap();
+        let mut program = Parser::new(&tokens).program().unwrap();
+        analyze(&mut program).unwrap();
+        assign_offsets(&mut program);
+        let offsets: Vec<usize> = program.locals.iter().map(|lvar| lvar.offset).collect();
+        assert_eq!(offsets, vec![8, 16]);
+    }
+
+    #[test]
+    fn alignment() {
+        let lvar = |ty: Type| LVar {
+            name: "x".to_string(),
+            ty,
+            span: Span::default(),
+            offset: 0,
+        };
+        let mut program = Program {
+            stmts: vec![],
+            locals: vec![
+                lvar(Type::Char),
+                lvar(Type::Int),
+                lvar(Type::Char),
+                lvar(Type::Char),
+                lvar(Type::pointer_to(Type::Char)),
+            ],
+        };
+        assign_offsets(&mut program);
+        let offsets: Vec<usize> = program.locals.iter().map(|lvar| lvar.offset).collect();
+        assert_eq!(offsets, vec![1, 16, 17, 18, 32]);
+    }
+}
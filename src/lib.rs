@@ -0,0 +1,207 @@
+//! A compiler for a small subset of C that emits x86-64 assembly.
+//!
+//! `compile` runs the whole pipeline on one source text, and `tokenize` and
+//! `parse` stop after the front end. `run` interprets a program directly,
+//! as a reference to check the generated code against. The front end
+//! (`token`, `parser` and `ast`), the IR (`ir`) and the assembly
+//! representation (`x86`) are public for tools that want to look at a
+//! single stage.
+
+pub mod ast;
+pub mod diagnostic;
+pub mod dump;
+pub mod elf;
+mod encode;
+mod fold;
+mod generator;
+pub mod interp;
+pub mod ir;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod jit;
+mod layout;
+pub mod link;
+mod lower;
+pub mod opt;
+pub mod parser;
+mod peephole;
+mod reach;
+mod regalloc;
+mod sema;
+pub mod span;
+mod ssa;
+pub mod token;
+pub mod types;
+pub mod x86;
+
+use diagnostic::Diagnostic;
+use opt::{OptLevel, PassManager};
+use parser::Parser;
+use span::FileId;
+use token::Token;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub level: OptLevel,
+    /// Warn about code that can never be executed.
+    pub warn_unreachable: bool,
+    /// The file spans refer to, for callers that keep several sources in
+    /// one `SourceMap`.
+    pub file: FileId,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            level: OptLevel::O0,
+            warn_unreachable: false,
+            file: FileId(0),
+        }
+    }
+}
+
+/// Instruction counts around the peephole optimizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub before_peephole: usize,
+    pub after_peephole: usize,
+}
+
+#[derive(Debug)]
+pub struct Output {
+    /// The optimized IR the assembly was generated from.
+    pub ir: ir::Module,
+    pub assembly: x86::Program,
+    pub warnings: Vec<Diagnostic>,
+    pub stats: Stats,
+}
+
+/// Splits `source` into tokens, ending with an end-of-file token.
+pub fn tokenize(source: &str, options: &Options) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    Token::parse(options.file, source).map_err(|e| vec![e])
+}
+
+/// Parses `source` without checking it, so variables are unresolved and
+/// there are no locals.
+pub fn parse_syntax(source: &str, options: &Options) -> Result<ast::Program, Vec<Diagnostic>> {
+    let tokens = tokenize(source, options)?;
+    Parser::new(&tokens).program()
+}
+
+/// Runs semantic analysis on `program`, which resolves variables and
+/// collects the locals. On failure, what was resolved before the errors
+/// stays resolved.
+pub fn analyze(program: &mut ast::Program) -> Result<(), Vec<Diagnostic>> {
+    sema::analyze(program)
+}
+
+/// Parses `source` and runs semantic analysis on it.
+pub fn parse(source: &str, options: &Options) -> Result<ast::Program, Vec<Diagnostic>> {
+    let mut program = parse_syntax(source, options)?;
+    analyze(&mut program)?;
+    Ok(program)
+}
+
+/// Compiles `source` to assembly. On failure, returns the errors found;
+/// warnings only come with a successful result.
+pub fn compile(source: &str, options: &Options) -> Result<Output, Vec<Diagnostic>> {
+    let mut program = parse(source, options)?;
+    let mut warnings = fold::fold(&mut program);
+    layout::assign_offsets(&mut program);
+    let mut module = lower::lower(&program);
+    for function in &mut module.functions {
+        let mut unreachable = reach::remove_unreachable_code(function);
+        if options.warn_unreachable {
+            warnings.append(&mut unreachable);
+        }
+    }
+    PassManager::new(options.level).run(&mut module);
+    let mut assembly = generator::gen_program(&module);
+    let before_peephole = assembly.instruction_count();
+    peephole::optimize(&mut assembly);
+    let stats = Stats {
+        before_peephole,
+        after_peephole: assembly.instruction_count(),
+    };
+    Ok(Output {
+        ir: module,
+        assembly,
+        warnings,
+        stats,
+    })
+}
+
+/// Interprets `source` and returns the value `main` would return, without
+/// compiling it.
+pub fn run(source: &str, options: &Options) -> Result<i64, Vec<Diagnostic>> {
+    let mut program = parse(source, options)?;
+    layout::assign_offsets(&mut program);
+    interp::run(&program).map_err(|e| vec![e])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opt::OptLevel;
+    use crate::parser::MAX_DEPTH;
+    use crate::{compile, run, Options};
+
+    #[test]
+    fn compiles() {
+        let options = Options {
+            level: OptLevel::O2,
+            warn_unreachable: true,
+            ..Options::default()
+        };
+        let output = compile("a = 6; return a * 7; a / 0;", &options).unwrap();
+        assert!(output.assembly.to_string().contains("main:"));
+        let warnings: Vec<&str> = output
+            .warnings
+            .iter()
+            .map(|warning| warning.message.as_str())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "division by zero [-Wdiv-by-zero]",
+                "code will never be executed [-Wunreachable-code]"
+            ]
+        );
+        assert!(output.stats.after_peephole <= output.stats.before_peephole);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let errors = compile("a + 1; b;", &Options::default()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].span.column, 8);
+    }
+
+    #[test]
+    fn compiles_the_deepest_expressions() {
+        // Runs with the stack of a main thread, since test threads have less.
+        let test = || {
+            let depth = MAX_DEPTH;
+            let inputs = [
+                format!("{}1{};", "(".repeat(depth), ")".repeat(depth)),
+                format!("{}1{};", "(1 + ".repeat(depth - 1), ")".repeat(depth - 1)),
+                format!("{}1; a;", "a = ".repeat(depth - 1)),
+            ];
+            let expected = [1, depth as i64, 1];
+            for level in [OptLevel::O0, OptLevel::O2] {
+                let options = Options {
+                    level,
+                    ..Options::default()
+                };
+                for (input, expected) in inputs.iter().zip(expected) {
+                    compile(input, &options).unwrap();
+                    assert_eq!(run(input, &options).unwrap(), expected);
+                }
+            }
+        };
+        std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,629 @@
+//! A static linker for programs that need nothing from the C library. It
+//! reads ELF64 relocatable objects, merges their sections into a text, a
+//! read-only and a writable segment, resolves the symbols, applies the
+//! relocations and writes an executable. The entry point is a small
+//! `_start` that calls `main` and passes its result to the `exit` system
+//! call, so the executable runs without libc or its start files.
+
+use crate::elf::{
+    EHDR_SIZE, EM_X86_64, ET_REL, R_X86_64_PC32, R_X86_64_PLT32, SHF_ALLOC, SHF_EXECINSTR,
+    SHF_WRITE, SHT_NOBITS, SHT_PROGBITS, SHT_RELA, SHT_SYMTAB, STB_GLOBAL,
+};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// Where the first segment is loaded, as with the system linker.
+const BASE: u64 = 0x40_0000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const ET_EXEC: u16 = 2;
+const PHDR_SIZE: u16 = 56;
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+
+/// `xor ebp, ebp; call main; mov rdi, rax; mov eax, 60; syscall`, which
+/// marks the outermost frame, runs the program and exits with the value
+/// `main` returns. The `call` is relocated like any other.
+const START: [u8; 17] = [
+    0x31, 0xed, 0xe8, 0, 0, 0, 0, 0x48, 0x89, 0xc7, 0xb8, 0x3c, 0, 0, 0, 0x0f, 0x05,
+];
+
+/// The segment a loaded section goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Text,
+    Rodata,
+    Data,
+    Bss,
+}
+
+const CLASSES: [Class; 4] = [Class::Text, Class::Rodata, Class::Data, Class::Bss];
+
+struct InputSection {
+    class: Class,
+    /// The contents, empty for `.bss`.
+    bytes: Vec<u8>,
+    size: u64,
+    align: u64,
+}
+
+struct Symbol {
+    name: String,
+    global: bool,
+    section: u16,
+    value: u64,
+}
+
+struct Relocation {
+    /// The index of the section the relocation applies to.
+    section: usize,
+    offset: u64,
+    symbol: usize,
+    kind: u32,
+    addend: i64,
+}
+
+struct Object {
+    name: String,
+    /// By section index; `None` for sections that are not loaded, such as
+    /// the symbol table.
+    sections: Vec<Option<InputSection>>,
+    symbols: Vec<Symbol>,
+    relocations: Vec<Relocation>,
+}
+
+/// Links `objects`, each given by its name and contents, into the bytes of
+/// a static executable.
+pub fn link(objects: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut inputs = vec![start()];
+    for (name, bytes) in objects {
+        inputs.push(read(name, bytes).map_err(|message| format!("{}: {}", name, message))?);
+    }
+
+    // Lay out each class of sections on its own, starting at 0.
+    let mut sizes = [0u64; 4];
+    let mut aligns = [1u64; 4];
+    let mut offsets: Vec<Vec<Option<u64>>> = vec![];
+    let too_large = || "output too large".to_string();
+    for object in &inputs {
+        let mut object_offsets = vec![];
+        for section in &object.sections {
+            object_offsets.push(match section {
+                Some(section) => {
+                    let class = section.class as usize;
+                    let align = section.align.max(1);
+                    let offset = sizes[class]
+                        .checked_next_multiple_of(align)
+                        .ok_or_else(too_large)?;
+                    sizes[class] = offset.checked_add(section.size).ok_or_else(too_large)?;
+                    aligns[class] = aligns[class].max(align);
+                    Some(offset)
+                }
+                None => None,
+            });
+        }
+        offsets.push(object_offsets);
+    }
+
+    // Then place the classes: text after the headers, read-only data and
+    // writable data on pages of their own, and .bss right after .data.
+    let has_rodata = sizes[Class::Rodata as usize] > 0;
+    let has_data = sizes[Class::Data as usize] > 0 || sizes[Class::Bss as usize] > 0;
+    let segments = 2 + has_rodata as u16 + has_data as u16;
+    let headers = EHDR_SIZE as u64 + PHDR_SIZE as u64 * segments as u64;
+    let mut starts = [0u64; 4];
+    let mut end = headers;
+    for class in CLASSES {
+        let align = match class {
+            Class::Rodata | Class::Data => PAGE_SIZE,
+            Class::Text | Class::Bss => aligns[class as usize],
+        };
+        starts[class as usize] = end.checked_next_multiple_of(align).ok_or_else(too_large)?;
+        end = starts[class as usize]
+            .checked_add(sizes[class as usize])
+            .ok_or_else(too_large)?;
+    }
+    // Every address in the image is BASE plus an offset of at most `end`.
+    end.checked_add(BASE).ok_or_else(too_large)?;
+    let file_size = starts[Class::Data as usize] + sizes[Class::Data as usize];
+    let mut image = vec![0; file_size as usize];
+
+    let mut addresses: Vec<Vec<Option<u64>>> = vec![];
+    for (object, object_offsets) in inputs.iter().zip(&offsets) {
+        let mut object_addresses = vec![];
+        for (section, offset) in object.sections.iter().zip(object_offsets) {
+            object_addresses.push(match (section, offset) {
+                (Some(section), Some(offset)) => {
+                    let start = (starts[section.class as usize] + offset) as usize;
+                    // .bss has no bytes and may start past the end of the file.
+                    if section.class != Class::Bss {
+                        image[start..start + section.bytes.len()].copy_from_slice(&section.bytes);
+                    }
+                    Some(BASE + start as u64)
+                }
+                _ => None,
+            });
+        }
+        addresses.push(object_addresses);
+    }
+
+    let mut globals = HashMap::new();
+    for (object, object_addresses) in inputs.iter().zip(&addresses) {
+        for symbol in &object.symbols {
+            if !symbol.global || symbol.section == SHN_UNDEF {
+                continue;
+            }
+            let value = defined_value(object, object_addresses, symbol)?;
+            if globals.insert(symbol.name.as_str(), value).is_some() {
+                return Err(format!("multiple definition of '{}'", symbol.name));
+            }
+        }
+    }
+
+    for (object, object_addresses) in inputs.iter().zip(&addresses) {
+        for relocation in &object.relocations {
+            // Relocations of sections that are not loaded, such as
+            // .eh_frame, do not matter.
+            let place = match object_addresses[relocation.section] {
+                Some(address) => address
+                    .checked_add(relocation.offset)
+                    .ok_or_else(|| format!("{}: invalid relocation", object.name))?,
+                None => continue,
+            };
+            let symbol = &object.symbols[relocation.symbol];
+            let value = match symbol.section {
+                SHN_UNDEF if relocation.symbol == 0 => 0,
+                SHN_UNDEF => *globals.get(symbol.name.as_str()).ok_or_else(|| {
+                    format!("{}: undefined reference to '{}'", object.name, symbol.name)
+                })?,
+                _ => defined_value(object, object_addresses, symbol)?,
+            };
+            let value = value.wrapping_add(relocation.addend as u64);
+            let at = (place - BASE) as usize;
+            let fits = |fits: bool, bytes: Vec<u8>| {
+                if fits {
+                    Ok(bytes)
+                } else {
+                    Err(format!(
+                        "{}: relocation truncated to fit against '{}'",
+                        object.name, symbol.name
+                    ))
+                }
+            };
+            let bytes = match relocation.kind {
+                R_X86_64_64 => value.to_le_bytes().to_vec(),
+                R_X86_64_PC32 | R_X86_64_PLT32 => {
+                    let value = value.wrapping_sub(place) as i64;
+                    fits(
+                        value as i32 as i64 == value,
+                        (value as i32).to_le_bytes().to_vec(),
+                    )?
+                }
+                R_X86_64_32 => fits(
+                    value as u32 as u64 == value,
+                    (value as u32).to_le_bytes().to_vec(),
+                )?,
+                R_X86_64_32S => fits(
+                    value as i64 as i32 as i64 == value as i64,
+                    (value as i32).to_le_bytes().to_vec(),
+                )?,
+                kind => {
+                    return Err(format!(
+                        "{}: unsupported relocation type {}",
+                        object.name, kind
+                    ))
+                }
+            };
+            match at
+                .checked_add(bytes.len())
+                .and_then(|end| image.get_mut(at..end))
+            {
+                Some(field) => field.copy_from_slice(&bytes),
+                None => return Err(format!("{}: invalid relocation", object.name)),
+            }
+        }
+    }
+
+    let entry = addresses[0][1].expect("_start is loaded");
+    let mut header = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+    header.resize(16, 0);
+    header.extend(ET_EXEC.to_le_bytes());
+    header.extend(EM_X86_64.to_le_bytes());
+    header.extend(1u32.to_le_bytes());
+    header.extend(entry.to_le_bytes());
+    header.extend((EHDR_SIZE as u64).to_le_bytes()); // program headers
+    header.extend(0u64.to_le_bytes()); // section headers
+    header.extend(0u32.to_le_bytes()); // flags
+    header.extend(EHDR_SIZE.to_le_bytes());
+    header.extend(PHDR_SIZE.to_le_bytes());
+    header.extend(segments.to_le_bytes());
+    header.extend([0; 6]); // no section headers
+    let text_end = starts[Class::Text as usize] + sizes[Class::Text as usize];
+    segment(&mut header, PT_LOAD, PF_R | PF_X, 0, text_end, text_end);
+    if has_rodata {
+        let start = starts[Class::Rodata as usize];
+        let size = sizes[Class::Rodata as usize];
+        segment(&mut header, PT_LOAD, PF_R, start, size, size);
+    }
+    if has_data {
+        let start = starts[Class::Data as usize];
+        let memory_size = starts[Class::Bss as usize] + sizes[Class::Bss as usize] - start;
+        segment(
+            &mut header,
+            PT_LOAD,
+            PF_R | PF_W,
+            start,
+            sizes[Class::Data as usize],
+            memory_size,
+        );
+    }
+    // Keeps the stack from being executable.
+    segment(&mut header, PT_GNU_STACK, PF_R | PF_W, 0, 0, 0);
+    image[..headers as usize].copy_from_slice(&header);
+    Ok(image)
+}
+
+/// Appends an `Elf64_Phdr`. Loaded segments sit at `BASE` plus their
+/// offset in the file.
+fn segment(
+    out: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    file_size: u64,
+    memory_size: u64,
+) {
+    let (address, align) = match kind {
+        PT_LOAD => (BASE + offset, PAGE_SIZE),
+        _ => (0, 16),
+    };
+    out.extend(kind.to_le_bytes());
+    out.extend(flags.to_le_bytes());
+    out.extend(offset.to_le_bytes());
+    out.extend(address.to_le_bytes());
+    out.extend(address.to_le_bytes());
+    out.extend(file_size.to_le_bytes());
+    out.extend(memory_size.to_le_bytes());
+    out.extend(align.to_le_bytes());
+}
+
+/// The address of `symbol`, which is defined in `object`.
+fn defined_value(
+    object: &Object,
+    addresses: &[Option<u64>],
+    symbol: &Symbol,
+) -> Result<u64, String> {
+    match symbol.section {
+        SHN_ABS => Ok(symbol.value),
+        section => match addresses.get(section as usize) {
+            Some(Some(address)) => Ok(address.wrapping_add(symbol.value)),
+            _ => Err(format!(
+                "{}: '{}' is not in a loaded section",
+                object.name, symbol.name
+            )),
+        },
+    }
+}
+
+/// The object holding `_start`.
+fn start() -> Object {
+    let symbol = |name: &str, section| Symbol {
+        name: name.to_string(),
+        global: true,
+        section,
+        value: 0,
+    };
+    Object {
+        name: "_start".to_string(),
+        sections: vec![
+            None,
+            Some(InputSection {
+                class: Class::Text,
+                bytes: START.to_vec(),
+                size: START.len() as u64,
+                align: 16,
+            }),
+        ],
+        symbols: vec![
+            symbol("", SHN_UNDEF),
+            symbol("_start", 1),
+            symbol("main", SHN_UNDEF),
+        ],
+        relocations: vec![Relocation {
+            section: 1,
+            offset: 3,
+            symbol: 2,
+            kind: R_X86_64_PLT32,
+            addend: -4,
+        }],
+    }
+}
+
+/// Bounds-checked little-endian reads from an object file.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, offset: u64, len: u64) -> Result<&'a [u8], String> {
+        let start = offset as usize;
+        match start.checked_add(len as usize) {
+            Some(end) if end <= self.0.len() => Ok(&self.0[start..end]),
+            _ => Err("truncated object file".to_string()),
+        }
+    }
+
+    /// The offsets `offset..offset + len`, which must be in the file.
+    fn range(&self, offset: u64, len: u64) -> Result<Range<u64>, String> {
+        self.slice(offset, len)?;
+        Ok(offset..offset + len)
+    }
+
+    fn u8(&self, offset: u64) -> Result<u8, String> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(
+            self.slice(offset, 2)?.try_into().unwrap(),
+        ))
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(
+            self.slice(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(
+            self.slice(offset, 8)?.try_into().unwrap(),
+        ))
+    }
+
+    /// The NUL-terminated string at `offset`.
+    fn string(&self, offset: u64) -> Result<String, String> {
+        let rest = self
+            .0
+            .get(offset as usize..)
+            .ok_or("truncated object file")?;
+        match rest.iter().position(|byte| *byte == 0) {
+            Some(end) => Ok(String::from_utf8_lossy(&rest[..end]).into_owned()),
+            None => Err("truncated object file".to_string()),
+        }
+    }
+}
+
+/// A section header, as far as the linker cares.
+struct Header {
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+}
+
+/// Reads the relocatable object `bytes`.
+fn read(name: &str, bytes: &[u8]) -> Result<Object, String> {
+    let bytes = Bytes(bytes);
+    let ident = bytes.slice(0, 8).unwrap_or_default();
+    if ident[..] != [0x7f, b'E', b'L', b'F', 2, 1, 1, 0][..]
+        || bytes.u16(16)? != ET_REL
+        || bytes.u16(18)? != EM_X86_64
+    {
+        return Err("not an ELF64 x86-64 relocatable object".to_string());
+    }
+    let table = bytes.u64(40)?;
+    let count = bytes.u16(60)? as u64;
+    let mut headers = vec![];
+    for at in bytes.range(table, 64 * count)?.step_by(64) {
+        headers.push(Header {
+            kind: bytes.u32(at + 4)?,
+            flags: bytes.u64(at + 8)?,
+            offset: bytes.u64(at + 24)?,
+            size: bytes.u64(at + 32)?,
+            link: bytes.u32(at + 40)?,
+            info: bytes.u32(at + 44)?,
+            align: bytes.u64(at + 48)?,
+        });
+    }
+
+    let mut object = Object {
+        name: name.to_string(),
+        sections: vec![],
+        symbols: vec![],
+        relocations: vec![],
+    };
+    for header in &headers {
+        let loaded = header.flags & SHF_ALLOC != 0;
+        let class = match header.kind {
+            SHT_PROGBITS if loaded && header.flags & SHF_EXECINSTR != 0 => Some(Class::Text),
+            SHT_PROGBITS if loaded && header.flags & SHF_WRITE != 0 => Some(Class::Data),
+            SHT_PROGBITS if loaded => Some(Class::Rodata),
+            SHT_NOBITS if loaded => Some(Class::Bss),
+            _ => None,
+        };
+        object.sections.push(match class {
+            Some(class) => Some(InputSection {
+                class,
+                bytes: match class {
+                    Class::Bss => vec![],
+                    _ => bytes.slice(header.offset, header.size)?.to_vec(),
+                },
+                size: header.size,
+                align: header.align,
+            }),
+            None => None,
+        });
+        match header.kind {
+            SHT_SYMTAB => {
+                let strings = headers
+                    .get(header.link as usize)
+                    .ok_or("invalid string table index")?
+                    .offset;
+                for at in bytes.range(header.offset, header.size)?.step_by(24) {
+                    let name = strings
+                        .checked_add(bytes.u32(at)? as u64)
+                        .ok_or("truncated object file")?;
+                    object.symbols.push(Symbol {
+                        name: bytes.string(name)?,
+                        global: bytes.u8(at + 4)? >> 4 == STB_GLOBAL,
+                        section: bytes.u16(at + 6)?,
+                        value: bytes.u64(at + 8)?,
+                    });
+                }
+            }
+            SHT_RELA => {
+                for at in bytes.range(header.offset, header.size)?.step_by(24) {
+                    let info = bytes.u64(at + 8)?;
+                    object.relocations.push(Relocation {
+                        section: header.info as usize,
+                        offset: bytes.u64(at)?,
+                        symbol: (info >> 32) as usize,
+                        kind: info as u32,
+                        addend: bytes.u64(at + 16)? as i64,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    for relocation in &object.relocations {
+        if relocation.symbol >= object.symbols.len() || relocation.section >= headers.len() {
+            return Err("invalid relocation".to_string());
+        }
+    }
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::object;
+    use crate::link::{link, BASE};
+    use crate::x86::{Function, Inst, Program};
+    use crate::{compile, Options};
+    use std::convert::TryInto;
+
+    fn compiled(input: &str) -> Vec<u8> {
+        object(&compile(input, &Options::default()).unwrap().assembly).unwrap()
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn links_executables() {
+        let executable = link(&[("a.o".to_string(), compiled("s = \"a\"; s;"))]).unwrap();
+        assert_eq!(&executable[..8], b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(executable[16], 2);
+        // Text, read-only data and the stack, with no writable data.
+        assert_eq!(executable[56], 3);
+        let entry = u64_at(&executable, 24);
+        let start = (entry - BASE) as usize;
+        assert_eq!(executable[start + 2], 0xe8);
+
+        // The call in _start lands on main, which follows it.
+        let call = i32::from_le_bytes(executable[start + 3..start + 7].try_into().unwrap());
+        let main = start + 7 + call as usize;
+        assert_eq!(main, (start + 17).next_multiple_of(16));
+        assert_eq!(executable[main], 0x55);
+
+        // The lea of the literal points at its bytes.
+        let lea = main
+            + executable[main..]
+                .windows(3)
+                .position(|w| w == [0x48, 0x8d, 0x05])
+                .unwrap();
+        let disp = i32::from_le_bytes(executable[lea + 3..lea + 7].try_into().unwrap());
+        let literal = (lea as i64 + 7 + disp as i64) as usize;
+        assert_eq!(&executable[literal..literal + 2], b"a\0");
+    }
+
+    fn set_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// The offset of the header of section `index` in `object`.
+    fn section_header(object: &[u8], index: usize) -> usize {
+        u64_at(object, 40) as usize + 64 * index
+    }
+
+    #[test]
+    fn links_bss_past_the_end_of_the_file() {
+        // What `as` makes of `.data; .byte 1; .bss; .align 8; .zero 8`: the
+        // .bss starts past the last byte of .data, and so of the file.
+        // Sections 2 and 3 of the objects `object` writes are .data and .bss.
+        let mut main = compiled("1;");
+        let data = section_header(&main, 2);
+        set_u64(&mut main, data + 32, 1);
+        let bss = section_header(&main, 3);
+        set_u64(&mut main, bss + 32, 8);
+        set_u64(&mut main, bss + 48, 8);
+        let executable = link(&[("a.o".to_string(), main)]).unwrap();
+        assert_eq!(executable.len(), 4097);
+        // The writable segment holds the byte of .data and ends with .bss.
+        let phdr = 64 + 56;
+        assert_eq!(u64_at(&executable, phdr + 8), 4096);
+        assert_eq!(u64_at(&executable, phdr + 32), 1);
+        assert_eq!(u64_at(&executable, phdr + 40), 16);
+    }
+
+    #[test]
+    fn rejects_truncated_objects() {
+        // Section 7 is the symbol table. Offsets and sizes that overflow
+        // must not wrap around into the file.
+        let main = compiled("1;");
+        let symtab = section_header(&main, 7);
+        let cases = [
+            (40, u64::MAX - 8),
+            (symtab + 24, u64::MAX - 8),
+            (symtab + 32, u64::MAX),
+        ];
+        for (offset, value) in cases {
+            let mut object = main.clone();
+            set_u64(&mut object, offset, value);
+            assert_eq!(
+                link(&[("a.o".to_string(), object)]).unwrap_err(),
+                "a.o: truncated object file"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let main = compiled("1;");
+        let objects = [("a.o".to_string(), main.clone()), ("b.o".to_string(), main)];
+        assert_eq!(link(&objects).unwrap_err(), "multiple definition of 'main'");
+
+        let program = Program {
+            data: vec![],
+            functions: vec![Function {
+                name: "start".to_string(),
+                insts: vec![Inst::Ret],
+            }],
+        };
+        let objects = [("a.o".to_string(), object(&program).unwrap())];
+        assert_eq!(
+            link(&objects).unwrap_err(),
+            "_start: undefined reference to 'main'"
+        );
+
+        let objects = [("a.o".to_string(), b"!<arch>\n".to_vec())];
+        assert_eq!(
+            link(&objects).unwrap_err(),
+            "a.o: not an ELF64 x86-64 relocatable object"
+        );
+    }
+}
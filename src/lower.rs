@@ -0,0 +1,213 @@
+use crate::ast::{Expr, Program, Stmt};
+use crate::ir::{Block, BlockId, Function, Inst, Module, Slot, SlotId, Terminator, VReg};
+use crate::span::Span;
+
+/// Lowers a checked program to IR as a single function, `main`.
+///
+/// Every `return` jumps to one exit block whose phi picks the returned value.
+/// Falling off the end returns the value of the last expression statement,
+/// as the stack-machine generator used to. Statements after a `return` are
+/// lowered into a block of their own that nothing jumps to.
+pub fn lower(program: &Program) -> Module {
+    let mut lowerer = Lowerer {
+        function: Function {
+            name: "main".to_string(),
+            blocks: vec![],
+            slots: program
+                .locals
+                .iter()
+                .map(|lvar| Slot {
+                    name: lvar.name.clone(),
+                    offset: lvar.offset,
+                })
+                .collect(),
+            vreg_count: 0,
+        },
+        blocks: vec![vec![]],
+        spans: vec![None],
+        current: Some(BlockId(0)),
+        returns: vec![],
+        strings: vec![],
+    };
+    let mut last = None;
+    for stmt in &program.stmts {
+        if lowerer.current.is_none() {
+            lowerer.current = Some(lowerer.new_block());
+            last = None;
+        }
+        let current = lowerer.current.unwrap();
+        if lowerer.spans[current.0].is_none() {
+            lowerer.spans[current.0] = Some(stmt.span());
+        }
+        match stmt {
+            Stmt::Expr { expr } => last = Some(lowerer.expr(expr)),
+            Stmt::Return { expr, .. } => {
+                let value = lowerer.expr(expr);
+                lowerer.ret(value);
+            }
+        }
+    }
+    if lowerer.current.is_some() {
+        let value = match last {
+            Some(value) => value,
+            None => lowerer.constant(0),
+        };
+        lowerer.ret(value);
+    }
+    lowerer.finish()
+}
+
+struct Lowerer<'a> {
+    function: Function,
+    /// Instructions of the blocks built so far. Each of them ends with a
+    /// jump to the exit block, which is added by `finish`.
+    blocks: Vec<Vec<Inst>>,
+    /// The first statement of each block.
+    spans: Vec<Option<Span>>,
+    /// The block being appended to, or `None` right after a `return`.
+    current: Option<BlockId>,
+    /// The value returned along each edge into the exit block.
+    returns: Vec<(BlockId, VReg)>,
+    strings: Vec<&'a str>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(vec![]);
+        self.spans.push(None);
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        let current = self.current.expect("no open block");
+        self.blocks[current.0].push(inst);
+    }
+
+    fn constant(&mut self, value: i64) -> VReg {
+        let dst = self.function.new_vreg();
+        self.emit(Inst::Const { dst, value });
+        dst
+    }
+
+    /// Closes the current block, which returns `value` through the exit block.
+    fn ret(&mut self, value: VReg) {
+        let current = self.current.take().expect("no open block");
+        self.returns.push((current, value));
+    }
+
+    fn finish(mut self) -> Module {
+        let exit = BlockId(self.blocks.len());
+        let dst = self.function.new_vreg();
+        for (insts, span) in self.blocks.into_iter().zip(self.spans) {
+            self.function.blocks.push(Block {
+                insts,
+                term: Terminator::Jump(exit),
+                span,
+            });
+        }
+        self.function.blocks.push(Block {
+            insts: vec![Inst::Phi {
+                dst,
+                args: self.returns,
+            }],
+            term: Terminator::Ret(dst),
+            span: None,
+        });
+        Module {
+            functions: vec![self.function],
+            strings: self.strings.into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> VReg {
+        match expr {
+            Expr::Num { value, .. } => self.constant(*value),
+            Expr::Str { value, .. } => {
+                let dst = self.function.new_vreg();
+                self.strings.push(value);
+                self.emit(Inst::Str {
+                    dst,
+                    index: self.strings.len() - 1,
+                });
+                dst
+            }
+            Expr::Var { id, .. } => {
+                let dst = self.function.new_vreg();
+                self.emit(Inst::Load {
+                    dst,
+                    slot: slot(id),
+                });
+                dst
+            }
+            Expr::Assign { lhs, rhs, .. } => {
+                let src = self.expr(rhs);
+                match &**lhs {
+                    Expr::Var { id, .. } => self.emit(Inst::Store {
+                        slot: slot(id),
+                        src,
+                    }),
+                    _ => unreachable!("lvalues are checked by semantic analysis"),
+                }
+                src
+            }
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                let dst = self.function.new_vreg();
+                self.emit(Inst::Binary {
+                    dst,
+                    op: *op,
+                    lhs,
+                    rhs,
+                });
+                dst
+            }
+        }
+    }
+}
+
+fn slot(id: &Option<usize>) -> SlotId {
+    SlotId(id.expect("variables are resolved by semantic analysis"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lower::lower;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::FileId;
+    use crate::token::Token;
+
+    fn lowered(input: &str) -> String {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        let mut program = Parser::new(&tokens).program().unwrap();
+        sema::analyze(&mut program).unwrap();
+        lower(&program).functions[0].to_string()
+    }
+
+    #[test]
+    fn straight_line() {
+        assert_eq!(
+            lowered("a = 3; b = a * 2; a + b;"),
+            "function main {\n\
+             bb0:\n  %0 = const 3\n  store $a, %0\n\
+             \x20 %1 = load $a\n  %2 = const 2\n  %3 = mul %1, %2\n  store $b, %3\n\
+             \x20 %4 = load $a\n  %5 = load $b\n  %6 = add %4, %5\n  jmp bb1\n\
+             bb1:\n  %7 = phi [bb0: %6]\n  ret %7\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn statements_after_return() {
+        assert_eq!(
+            lowered("return 1; 2; return 3; 4;"),
+            "function main {\n\
+             bb0:\n  %0 = const 1\n  jmp bb3\n\
+             bb1:\n  %1 = const 2\n  %2 = const 3\n  jmp bb3\n\
+             bb2:\n  %3 = const 4\n  jmp bb3\n\
+             bb3:\n  %4 = phi [bb0: %0], [bb1: %2], [bb2: %3]\n  ret %4\n\
+             }\n"
+        );
+    }
+}
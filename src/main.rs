@@ -1,19 +1,352 @@
-mod node;
-mod token;
-mod generator;
+mod cli;
+mod toolchain;
 
-use node::Parser;
+use cli::{Build, Command, Input, Output, Stage};
+use nine_cc::diagnostic::Diagnostic;
+use nine_cc::dump;
+use nine_cc::elf;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use nine_cc::jit::Jit;
+use nine_cc::link;
+use nine_cc::span::SourceMap;
+use nine_cc::{analyze, compile, parse_syntax, tokenize, Options};
 use std::env;
-use token::Token;
-use generator::gen_program;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use toolchain::TempFiles;
+
+/// Reads `input` into `sources`. Returns the options to compile it with,
+/// whose `file` identifies it.
+fn load(input: &Input, options: &Options, sources: &mut SourceMap) -> Option<Options> {
+    let text = match input {
+        Input::Stdin => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map(|_| text)
+        }
+        Input::File(path) | Input::Object(path) => fs::read_to_string(path),
+    };
+    match text {
+        Ok(text) => Some(Options {
+            file: sources.add(input.name().to_string(), text),
+            ..options.clone()
+        }),
+        Err(error) => {
+            eprintln!("nine-cc: error: {}: {}", input.name(), error);
+            None
+        }
+    }
+}
+
+fn report(diagnostics: &[Diagnostic], sources: &SourceMap) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(sources));
+    }
+}
+
+/// What compiling one input produces for the stage asked for.
+enum Product {
+    Text(String),
+    /// Assembly to be assembled by `as`, for `-fno-integrated-as`.
+    Assembly(String),
+    Object(Vec<u8>),
+}
+
+/// Reads and compiles `input`, reporting its diagnostics on stderr.
+/// Returns the output `build` asks for, where executables start out as
+/// objects.
+fn compile_input(input: &Input, build: &Build, sources: &mut SourceMap) -> Option<Product> {
+    let options = load(input, &build.options, sources)?;
+    match compile(&sources.get(options.file).text, &options) {
+        Ok(output) => {
+            report(&output.warnings, sources);
+            if build.stats {
+                eprintln!(
+                    "instructions: {} before peephole, {} after",
+                    output.stats.before_peephole, output.stats.after_peephole
+                );
+            }
+            match build.stage {
+                Stage::Ir => Some(Product::Text(output.ir.to_string())),
+                Stage::Dot => Some(Product::Text(output.ir.to_dot())),
+                Stage::Assembly => Some(Product::Text(output.assembly.to_string())),
+                Stage::Object | Stage::Executable if !build.integrated_as => {
+                    Some(Product::Assembly(output.assembly.to_string()))
+                }
+                Stage::Object | Stage::Executable => match elf::object(&output.assembly) {
+                    Ok(bytes) => Some(Product::Object(bytes)),
+                    Err(message) => {
+                        eprintln!("nine-cc: error: {}: {}", input.name(), message);
+                        None
+                    }
+                },
+            }
+        }
+        Err(errors) => {
+            report(&errors, sources);
+            None
+        }
+    }
+}
+
+/// Prints the dumps `build` asks for of `input` to stdout.
+fn dump_input(input: &Input, build: &Build, sources: &mut SourceMap) -> bool {
+    let options = match load(input, &build.options, sources) {
+        Some(options) => options,
+        None => return false,
+    };
+    let text = &sources.get(options.file).text;
+    let result = tokenize(text, &options).and_then(|tokens| {
+        if let Some(format) = build.dump_tokens {
+            print!("{}", dump::tokens(&tokens, format));
+        }
+        match build.dump_ast {
+            // The tree is dumped even if it does not check, since that is
+            // when seeing it helps most.
+            Some(format) => parse_syntax(text, &options).and_then(|mut program| {
+                let analyzed = analyze(&mut program);
+                print!("{}", dump::ast(&program, format));
+                analyzed
+            }),
+            None => Ok(()),
+        }
+    });
+    if let Err(errors) = result {
+        report(&errors, sources);
+        return false;
+    }
+    true
+}
+
+/// Interprets the single input of `build`. Returns the exit status: the
+/// value the program returns, truncated as the shell sees it, or 1 if it
+/// cannot be run.
+fn interpret(build: &Build) -> i32 {
+    let mut sources = SourceMap::new();
+    let options = match load(&build.inputs[0], &build.options, &mut sources) {
+        Some(options) => options,
+        None => return 1,
+    };
+    match nine_cc::run(&sources.get(options.file).text, &options) {
+        Ok(value) => value as i32,
+        Err(errors) => {
+            report(&errors, &sources);
+            1
+        }
+    }
+}
+
+/// Compiles the single input of `build` and runs it in-process. Returns
+/// the exit status as `interpret` does.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn execute(build: &Build) -> i32 {
+    let mut sources = SourceMap::new();
+    let options = match load(&build.inputs[0], &build.options, &mut sources) {
+        Some(options) => options,
+        None => return 1,
+    };
+    let output = match compile(&sources.get(options.file).text, &options) {
+        Ok(output) => output,
+        Err(errors) => {
+            report(&errors, &sources);
+            return 1;
+        }
+    };
+    report(&output.warnings, &sources);
+    match Jit::load(&output.assembly) {
+        // The code comes from `compile`, which is what `run_main` needs.
+        Ok(jit) => unsafe { jit.run_main() as i32 },
+        Err(message) => {
+            eprintln!("nine-cc: error: {}", message);
+            1
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn execute(_: &Build) -> i32 {
+    eprintln!("nine-cc: error: '--jit' is only supported on x86-64 Linux");
+    1
+}
+
+/// Runs `produce` to create the file `output`. Tools can only write to a
+/// path, so output for stdout goes through a temporary file.
+fn produce(
+    output: &Output,
+    temps: &mut TempFiles,
+    produce: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    match output {
+        Output::File(path) => produce(Path::new(path)),
+        Output::Stdout => {
+            let temp = temps.create("out")?;
+            produce(&temp)?;
+            let bytes = fs::read(&temp).map_err(|error| error.to_string())?;
+            io::stdout()
+                .write_all(&bytes)
+                .map_err(|error| error.to_string())
+        }
+    }
+}
+
+fn write(output: &Output, bytes: &[u8]) -> Result<(), String> {
+    match output {
+        Output::Stdout => io::stdout()
+            .write_all(bytes)
+            .map_err(|error| error.to_string()),
+        Output::File(path) => {
+            fs::write(path, bytes).map_err(|error| format!("cannot write '{}': {}", path, error))
+        }
+    }
+}
+
+/// Writes what was compiled from one input to `output`, assembling it
+/// first if needed. When `stage` is linking, the object goes to a
+/// temporary file added to `objects` instead.
+fn emit(
+    product: Product,
+    stage: Stage,
+    output: &Output,
+    temps: &mut TempFiles,
+    objects: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    match product {
+        Product::Text(text) => write(output, text.as_bytes()),
+        Product::Assembly(text) => {
+            let source = temps.create("s")?;
+            fs::write(&source, text).map_err(|error| error.to_string())?;
+            if stage == Stage::Object {
+                produce(output, temps, |path| toolchain::assemble(&source, path))
+            } else {
+                let object = temps.create("o")?;
+                toolchain::assemble(&source, &object)?;
+                objects.push(object);
+                Ok(())
+            }
+        }
+        Product::Object(bytes) if stage == Stage::Object => write(output, &bytes),
+        Product::Object(bytes) => {
+            let object = temps.create("o")?;
+            fs::write(&object, bytes).map_err(|error| error.to_string())?;
+            objects.push(object);
+            Ok(())
+        }
+    }
+}
+
+/// Links `objects` into the executable `output` with the built-in linker.
+fn link_builtin(objects: &[PathBuf], output: &Path) -> Result<(), String> {
+    let mut inputs = vec![];
+    for path in objects {
+        let bytes = fs::read(path)
+            .map_err(|error| format!("cannot read '{}': {}", path.display(), error))?;
+        inputs.push((path.display().to_string(), bytes));
+    }
+    let executable = link::link(&inputs)?;
+    fs::write(output, executable)
+        .map_err(|error| format!("cannot write '{}': {}", output.display(), error))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+/// Takes every input as far as the stage `build` asks for, then links if
+/// that is the goal. Returns whether everything succeeded. Temporary files
+/// are gone by the time it returns.
+fn run(build: &Build) -> bool {
+    let mut sources = SourceMap::new();
+    let mut temps = TempFiles::new();
+    let mut objects = vec![];
+    let mut failed = false;
+    let stage = build.stage;
+    // Like a C compiler, keep going so that every input gets its diagnostics.
+    for input in &build.inputs {
+        if let Input::Object(path) = input {
+            if stage == Stage::Executable && !dumping(build) {
+                objects.push(PathBuf::from(path));
+            } else {
+                eprintln!(
+                    "nine-cc: warning: {}: linker input file unused because linking not done",
+                    path
+                );
+            }
+            continue;
+        }
+        if dumping(build) {
+            failed |= !dump_input(input, build, &mut sources);
+            continue;
+        }
+        let product = match compile_input(input, build, &mut sources) {
+            Some(product) => product,
+            None => {
+                failed = true;
+                continue;
+            }
+        };
+        let output = match &build.output {
+            Some(output) if stage != Stage::Executable => output.clone(),
+            _ => input.default_output(stage),
+        };
+        if let Err(message) = emit(product, stage, &output, &mut temps, &mut objects) {
+            eprintln!("nine-cc: error: {}", message);
+            failed = true;
+        }
+    }
+    if failed || stage != Stage::Executable || dumping(build) {
+        return !failed;
+    }
+    let output = match &build.output {
+        Some(output) => output.clone(),
+        None => Output::File("a.out".to_string()),
+    };
+    let linked = produce(&output, &mut temps, |path| {
+        if build.builtin_ld {
+            link_builtin(&objects, path)
+        } else {
+            toolchain::link(&objects, &build.link_args, path)
+        }
+    });
+    if let Err(message) = linked {
+        eprintln!("nine-cc: error: {}", message);
+        return false;
+    }
+    true
+}
+
+fn dumping(build: &Build) -> bool {
+    build.dump_tokens.is_some() || build.dump_ast.is_some()
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let arg: &String = &args[1];
-    let mut tokens = Token::parse(arg.to_string());
-    let program = Parser::new().program(&mut tokens);
-    let assembly = gen_program(&program);
-    for line in assembly {
-        println!("{}", line);
+    let build = match cli::parse(&args[1..]) {
+        Ok(Command::Help) => {
+            print!("{}\n\n{}", cli::USAGE, cli::HELP);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("nine-cc {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Ok(Command::Build(build)) => build,
+        Err(message) => {
+            eprintln!("nine-cc: error: {}", message);
+            eprintln!("{}", cli::USAGE);
+            process::exit(1);
+        }
+    };
+    if build.run {
+        process::exit(interpret(&build));
+    }
+    if build.jit {
+        process::exit(execute(&build));
+    }
+    if !run(&build) {
+        process::exit(1);
     }
 }
@@ -0,0 +1,73 @@
+//! Dead code elimination. Starting from terminators and instructions with
+//! side effects, everything they use is marked live; the rest is deleted.
+
+use crate::ir::{Function, Inst, VReg};
+
+pub fn run(function: &mut Function) -> bool {
+    let mut definitions: Vec<Option<&Inst>> = vec![None; function.vreg_count as usize];
+    let mut worklist: Vec<VReg> = vec![];
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Some(dst) = inst.dst() {
+                definitions[dst.0 as usize] = Some(inst);
+            }
+            if inst.has_side_effects() {
+                worklist.extend(inst.uses());
+            }
+        }
+        worklist.extend(block.term.uses());
+    }
+    let mut live = vec![false; function.vreg_count as usize];
+    while let Some(reg) = worklist.pop() {
+        if live[reg.0 as usize] {
+            continue;
+        }
+        live[reg.0 as usize] = true;
+        if let Some(inst) = definitions[reg.0 as usize] {
+            worklist.extend(inst.uses());
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let before = block.insts.len();
+        block.insts.retain(|inst| match inst.dst() {
+            Some(dst) => live[dst.0 as usize] || inst.has_side_effects(),
+            None => true,
+        });
+        changed |= block.insts.len() != before;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::tests::diamond;
+    use crate::ir::{Inst, SlotId, Terminator, VReg};
+    use crate::opt::dce::run;
+
+    #[test]
+    fn removes_unused_values() {
+        let mut function = diamond();
+        function.blocks[3].term = Terminator::Ret(VReg(0));
+        assert!(run(&mut function));
+        assert_eq!(function.blocks[0].insts.len(), 1);
+        for id in 1..4 {
+            assert_eq!(function.blocks[id].insts, vec![]);
+        }
+        assert!(!run(&mut function));
+    }
+
+    #[test]
+    fn keeps_stores() {
+        let mut function = diamond();
+        function.blocks[3].insts.push(Inst::Store {
+            slot: SlotId(0),
+            src: VReg(3),
+        });
+        function.blocks[3].term = Terminator::Ret(VReg(0));
+        assert!(run(&mut function));
+        assert_eq!(function.blocks[1].insts.len(), 1);
+        assert_eq!(function.blocks[3].insts.len(), 2);
+    }
+}
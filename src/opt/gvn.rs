@@ -0,0 +1,131 @@
+//! Global value numbering over the dominator tree. An instruction that
+//! computes the same value as one in a dominating block is deleted and its
+//! uses are redirected to the earlier result.
+
+use crate::ast::BinOp;
+use crate::ir::{BlockId, Function, Inst, VReg};
+use crate::ssa::Dominators;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Const(i64),
+    Str(usize),
+    Binary(BinOp, VReg, VReg),
+}
+
+struct Gvn {
+    children: Vec<Vec<BlockId>>,
+    /// Values available in the block being visited, from it and the blocks
+    /// dominating it.
+    available: HashMap<Key, VReg>,
+    replacements: HashMap<VReg, VReg>,
+}
+
+pub fn run(function: &mut Function) -> bool {
+    let mut gvn = Gvn {
+        children: Dominators::new(function).children(),
+        available: HashMap::new(),
+        replacements: HashMap::new(),
+    };
+    gvn.visit(function, BlockId(0));
+    if gvn.replacements.is_empty() {
+        return false;
+    }
+    function.replace_uses(&gvn.replacements);
+    true
+}
+
+impl Gvn {
+    fn resolve(&self, mut reg: VReg) -> VReg {
+        while let Some(replacement) = self.replacements.get(&reg) {
+            reg = *replacement;
+        }
+        reg
+    }
+
+    fn key(&self, inst: &Inst) -> Option<Key> {
+        match inst {
+            Inst::Const { value, .. } => Some(Key::Const(*value)),
+            Inst::Str { index, .. } => Some(Key::Str(*index)),
+            Inst::Binary { op, lhs, rhs, .. } => {
+                let (mut lhs, mut rhs) = (self.resolve(*lhs), self.resolve(*rhs));
+                if op.is_commutative() && rhs < lhs {
+                    std::mem::swap(&mut lhs, &mut rhs);
+                }
+                Some(Key::Binary(*op, lhs, rhs))
+            }
+            Inst::Load { .. } | Inst::Store { .. } | Inst::Phi { .. } | Inst::Copy { .. } => None,
+        }
+    }
+
+    fn visit(&mut self, function: &mut Function, block: BlockId) {
+        let mut added = vec![];
+        let insts = std::mem::take(&mut function.blocks[block.0].insts);
+        for inst in insts {
+            if let Inst::Copy { dst, src } = inst {
+                let src = self.resolve(src);
+                self.replacements.insert(dst, src);
+                continue;
+            }
+            if let Some(key) = self.key(&inst) {
+                let dst = inst.dst().unwrap();
+                if let Some(existing) = self.available.get(&key) {
+                    self.replacements.insert(dst, *existing);
+                    continue;
+                }
+                self.available.insert(key.clone(), dst);
+                added.push(key);
+            }
+            function.blocks[block.0].insts.push(inst);
+        }
+        for child in self.children[block.0].clone() {
+            self.visit(function, child);
+        }
+        for key in added {
+            self.available.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::BinOp;
+    use crate::ir::tests::diamond;
+    use crate::ir::{Inst, Terminator, VReg};
+    use crate::opt::gvn::run;
+
+    #[test]
+    fn reuses_dominating_values() {
+        // bb1 and bb3 both recompute the constant 1 that bb0 defines.
+        let mut function = diamond();
+        function.blocks[1].insts.push(Inst::Const {
+            dst: VReg(5),
+            value: 1,
+        });
+        function.blocks[3].insts.push(Inst::Binary {
+            dst: VReg(6),
+            op: BinOp::Add,
+            lhs: VReg(0),
+            rhs: VReg(3),
+        });
+        function.blocks[3].term = Terminator::Ret(VReg(6));
+        function.vreg_count = 7;
+        assert!(run(&mut function));
+        assert_eq!(function.blocks[1].insts.len(), 1);
+        assert_eq!(function.blocks[3].insts.len(), 2);
+        assert_eq!(function.blocks[3].term, Terminator::Ret(VReg(4)));
+        assert!(!run(&mut function));
+    }
+
+    #[test]
+    fn keeps_values_of_sibling_blocks() {
+        // bb2 computes 10 as bb1 does, but bb1 does not dominate it.
+        let mut function = diamond();
+        function.blocks[2].insts[0] = Inst::Const {
+            dst: VReg(2),
+            value: 10,
+        };
+        assert!(!run(&mut function));
+    }
+}
@@ -0,0 +1,134 @@
+//! Optimization passes over the IR. Every pass works on SSA form, which the
+//! pass manager builds first.
+
+mod dce;
+mod gvn;
+mod sccp;
+mod simplify_cfg;
+
+use crate::ir::{Function, Module};
+use crate::ssa;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+/// Upper bound on the rounds of `-O2`, which otherwise repeats its passes
+/// until none of them changes anything.
+const MAX_ROUNDS: usize = 10;
+
+/// Transforms a function in place and returns whether it changed anything.
+type Pass = fn(&mut Function) -> bool;
+
+pub struct PassManager {
+    passes: Vec<Pass>,
+    rounds: usize,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> PassManager {
+        match level {
+            OptLevel::O0 => PassManager {
+                passes: vec![],
+                rounds: 0,
+            },
+            OptLevel::O1 => PassManager {
+                passes: vec![sccp::run, dce::run, simplify_cfg::run],
+                rounds: 1,
+            },
+            OptLevel::O2 => PassManager {
+                passes: vec![sccp::run, gvn::run, dce::run, simplify_cfg::run],
+                rounds: MAX_ROUNDS,
+            },
+        }
+    }
+
+    pub fn run(&self, module: &mut Module) {
+        if self.passes.is_empty() {
+            return;
+        }
+        for function in &mut module.functions {
+            ssa::construct(function);
+            for _ in 0..self.rounds {
+                let mut changed = false;
+                for pass in &self.passes {
+                    changed |= pass(function);
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lower::lower;
+    use crate::opt::{OptLevel, PassManager};
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::FileId;
+    use crate::token::Token;
+
+    fn optimized(input: &str, level: OptLevel) -> String {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        let mut program = Parser::new(&tokens).program().unwrap();
+        sema::analyze(&mut program).unwrap();
+        let mut module = lower(&program);
+        PassManager::new(level).run(&mut module);
+        module.functions[0].to_string()
+    }
+
+    #[test]
+    fn folds_straight_line_code() {
+        let input = "a = 3; b = a * 2; return a + b;";
+        assert_eq!(
+            optimized(input, OptLevel::O1),
+            "function main {\nbb0:\n  %7 = const 9\n  ret %7\n}\n"
+        );
+        assert_eq!(
+            optimized(input, OptLevel::O2),
+            "function main {\nbb0:\n  %6 = const 9\n  ret %6\n}\n"
+        );
+    }
+
+    // The front end has no conditionals, so lowering never emits a branch.
+    // The richest CFG it builds is a return in the middle, which leaves an
+    // unreachable block joining the exit block's phi.
+    #[test]
+    fn folds_through_unreachable_returns() {
+        let input = "a = 2; return a * 3; a = 5; return a;";
+        assert_eq!(
+            optimized(input, OptLevel::O1),
+            "function main {\nbb0:\n  %6 = const 6\n  ret %6\n}\n"
+        );
+    }
+
+    #[test]
+    fn o2_reuses_values() {
+        let input = "s = \"xy\"; t = s + 1; return t - s + (t - s);";
+        assert_eq!(
+            optimized(input, OptLevel::O2),
+            "function main {\n\
+             bb0:\n  %0 = str .LC0\n  %2 = const 1\n  %3 = add %0, %2\n\
+             \x20 %6 = sub %3, %0\n  %10 = add %6, %6\n  ret %10\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn o0_keeps_the_lowered_code() {
+        let input = "a = 3; return a;";
+        assert_eq!(
+            optimized(input, OptLevel::O0),
+            "function main {\n\
+             bb0:\n  %0 = const 3\n  store $a, %0\n  %1 = load $a\n  jmp bb1\n\
+             bb1:\n  %2 = phi [bb0: %1]\n  ret %2\n\
+             }\n"
+        );
+    }
+}
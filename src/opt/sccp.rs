@@ -0,0 +1,249 @@
+//! Sparse conditional constant propagation (Wegman and Zadeck). Values are
+//! only propagated along edges that can execute, so constants found behind
+//! a constant branch do not get merged with the arm that is never taken.
+
+use crate::ir::{BlockId, Function, Inst, Terminator, VReg};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    /// Not known to be defined yet.
+    Unknown,
+    Const(i64),
+    /// Has more than one value at run time.
+    Varying,
+}
+
+impl Value {
+    fn meet(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Unknown, value) | (value, Value::Unknown) => value,
+            (Value::Const(a), Value::Const(b)) if a == b => Value::Const(a),
+            _ => Value::Varying,
+        }
+    }
+}
+
+/// A place where a register is used.
+#[derive(Debug, Clone, Copy)]
+enum User {
+    Inst(BlockId, usize),
+    Term(BlockId),
+}
+
+struct Sccp<'a> {
+    function: &'a Function,
+    values: Vec<Value>,
+    users: Vec<Vec<User>>,
+    visited: Vec<bool>,
+    /// Edges known to execute, as (from, to).
+    edges: Vec<(BlockId, BlockId)>,
+    flow_worklist: Vec<(BlockId, BlockId)>,
+    ssa_worklist: Vec<VReg>,
+}
+
+/// Replaces every register with a constant value by that constant and every
+/// branch on a constant by a jump. Returns whether anything changed.
+pub fn run(function: &mut Function) -> bool {
+    let values = analyze(function);
+    let mut changed = false;
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            if matches!(inst, Inst::Const { .. }) {
+                continue;
+            }
+            if let Some(dst) = inst.dst() {
+                if let Value::Const(value) = values[dst.0 as usize] {
+                    *inst = Inst::Const { dst, value };
+                    changed = true;
+                }
+            }
+        }
+        if let Terminator::Branch { cond, then, els } = block.term {
+            if let Value::Const(value) = values[cond.0 as usize] {
+                block.term = Terminator::Jump(if value != 0 { then } else { els });
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn analyze(function: &Function) -> Vec<Value> {
+    let mut users = vec![vec![]; function.vreg_count as usize];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for (index, inst) in block.insts.iter().enumerate() {
+            for reg in inst.uses() {
+                users[reg.0 as usize].push(User::Inst(BlockId(id), index));
+            }
+        }
+        for reg in block.term.uses() {
+            users[reg.0 as usize].push(User::Term(BlockId(id)));
+        }
+    }
+    let mut sccp = Sccp {
+        function,
+        values: vec![Value::Unknown; function.vreg_count as usize],
+        users,
+        visited: vec![false; function.blocks.len()],
+        edges: vec![],
+        flow_worklist: vec![],
+        ssa_worklist: vec![],
+    };
+    sccp.visit_block(BlockId(0));
+    loop {
+        if let Some((from, to)) = sccp.flow_worklist.pop() {
+            if sccp.edges.contains(&(from, to)) {
+                continue;
+            }
+            sccp.edges.push((from, to));
+            if sccp.visited[to.0] {
+                for index in 0..function.blocks[to.0].insts.len() {
+                    if matches!(function.blocks[to.0].insts[index], Inst::Phi { .. }) {
+                        sccp.visit_inst(to, index);
+                    }
+                }
+            } else {
+                sccp.visit_block(to);
+            }
+        } else if let Some(reg) = sccp.ssa_worklist.pop() {
+            for user in sccp.users[reg.0 as usize].clone() {
+                match user {
+                    User::Inst(block, index) if sccp.visited[block.0] => {
+                        sccp.visit_inst(block, index)
+                    }
+                    User::Term(block) if sccp.visited[block.0] => sccp.visit_term(block),
+                    _ => {}
+                }
+            }
+        } else {
+            break;
+        }
+    }
+    sccp.values
+}
+
+impl<'a> Sccp<'a> {
+    fn visit_block(&mut self, block: BlockId) {
+        self.visited[block.0] = true;
+        for index in 0..self.function.blocks[block.0].insts.len() {
+            self.visit_inst(block, index);
+        }
+        self.visit_term(block);
+    }
+
+    fn value(&self, reg: VReg) -> Value {
+        self.values[reg.0 as usize]
+    }
+
+    fn visit_inst(&mut self, block: BlockId, index: usize) {
+        let inst = &self.function.blocks[block.0].insts[index];
+        let value = match inst {
+            Inst::Const { value, .. } => Value::Const(*value),
+            Inst::Str { .. } | Inst::Load { .. } => Value::Varying,
+            Inst::Store { .. } => return,
+            Inst::Copy { src, .. } => self.value(*src),
+            Inst::Binary { op, lhs, rhs, .. } => match (self.value(*lhs), self.value(*rhs)) {
+                (Value::Const(lhs), Value::Const(rhs)) => match op.eval(lhs, rhs) {
+                    Some(value) => Value::Const(value),
+                    None => Value::Varying,
+                },
+                (Value::Varying, _) | (_, Value::Varying) => Value::Varying,
+                _ => Value::Unknown,
+            },
+            Inst::Phi { args, .. } => args
+                .iter()
+                .filter(|(pred, _)| self.edges.contains(&(*pred, block)))
+                .fold(Value::Unknown, |value, (_, arg)| {
+                    value.meet(self.value(*arg))
+                }),
+        };
+        let dst = inst.dst().unwrap();
+        // Values only ever move down the lattice, which bounds the work.
+        let old = self.value(dst);
+        let new = old.meet(value);
+        if new != old {
+            self.values[dst.0 as usize] = new;
+            self.ssa_worklist.push(dst);
+        }
+    }
+
+    fn visit_term(&mut self, block: BlockId) {
+        let targets = match &self.function.blocks[block.0].term {
+            Terminator::Ret(_) => vec![],
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch { cond, then, els } => match self.value(*cond) {
+                Value::Unknown => vec![],
+                Value::Const(0) => vec![*els],
+                Value::Const(_) => vec![*then],
+                Value::Varying => vec![*then, *els],
+            },
+        };
+        for target in targets {
+            self.flow_worklist.push((block, target));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::BinOp;
+    use crate::ir::tests::diamond;
+    use crate::ir::{BlockId, Inst, Terminator, VReg};
+    use crate::opt::sccp::run;
+
+    #[test]
+    fn folds_constant_branch() {
+        // The condition is 1, so only bb1 feeds the phi and the sum is 11.
+        let mut function = diamond();
+        assert!(run(&mut function));
+        assert_eq!(function.blocks[0].term, Terminator::Jump(BlockId(1)));
+        assert_eq!(
+            function.blocks[3].insts[1],
+            Inst::Const {
+                dst: VReg(4),
+                value: 11
+            }
+        );
+        assert!(!run(&mut function));
+    }
+
+    #[test]
+    fn merges_arms() {
+        // With a condition that is not constant, the phi merges 10 and 20.
+        let mut function = diamond();
+        function.blocks[0].insts[0] = Inst::Str {
+            dst: VReg(0),
+            index: 0,
+        };
+        assert!(!run(&mut function));
+        function.blocks[2].insts[0] = Inst::Const {
+            dst: VReg(2),
+            value: 10,
+        };
+        assert!(run(&mut function));
+        assert_eq!(
+            function.blocks[3].insts[0],
+            Inst::Const {
+                dst: VReg(3),
+                value: 10
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_division_by_zero() {
+        let mut function = diamond();
+        function.blocks[3].insts[1] = Inst::Binary {
+            dst: VReg(4),
+            op: BinOp::Div,
+            lhs: VReg(3),
+            rhs: VReg(3),
+        };
+        function.blocks[1].insts[0] = Inst::Const {
+            dst: VReg(1),
+            value: 0,
+        };
+        run(&mut function);
+        assert!(matches!(function.blocks[3].insts[1], Inst::Binary { .. }));
+    }
+}
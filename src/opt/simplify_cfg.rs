@@ -0,0 +1,144 @@
+//! Control-flow graph simplification: deletes unreachable blocks, removes
+//! phis that do not choose between different values, turns branches with
+//! identical targets into jumps and merges blocks into their only
+//! predecessor.
+
+use crate::ir::{Block, BlockId, Function, Inst, Terminator};
+use std::collections::HashMap;
+
+pub fn run(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut round = function.remove_unreachable_blocks();
+        round |= remove_trivial_phis(function);
+        for block in &mut function.blocks {
+            if let Terminator::Branch { then, els, .. } = block.term {
+                if then == els {
+                    block.term = Terminator::Jump(then);
+                    round = true;
+                }
+            }
+        }
+        round |= merge_blocks(function);
+        if !round {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Replaces each phi whose arguments are all the same value, apart from the
+/// phi itself, by that value.
+fn remove_trivial_phis(function: &mut Function) -> bool {
+    let mut replacements = HashMap::new();
+    for block in &mut function.blocks {
+        block.insts.retain(|inst| match inst {
+            Inst::Phi { dst, args } => {
+                let mut values = args.iter().map(|(_, value)| *value).filter(|v| v != dst);
+                let first = match values.next() {
+                    Some(first) => first,
+                    None => return true,
+                };
+                if values.all(|value| value == first) {
+                    replacements.insert(*dst, first);
+                    false
+                } else {
+                    true
+                }
+            }
+            _ => true,
+        });
+    }
+    function.replace_uses(&replacements);
+    !replacements.is_empty()
+}
+
+/// Appends each block to its predecessor when that predecessor jumps to it
+/// and is its only one.
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut preds = function.predecessors();
+    let mut keep = vec![true; function.blocks.len()];
+    for id in 1..function.blocks.len() {
+        let pred = match preds[id].as_slice() {
+            [pred] if pred.0 != id => *pred,
+            _ => continue,
+        };
+        if function.blocks[pred.0].term != Terminator::Jump(BlockId(id))
+            || function.blocks[id]
+                .insts
+                .iter()
+                .any(|inst| matches!(inst, Inst::Phi { .. }))
+        {
+            continue;
+        }
+        let block = std::mem::replace(
+            &mut function.blocks[id],
+            Block {
+                insts: vec![],
+                term: Terminator::Jump(BlockId(id)),
+                span: None,
+            },
+        );
+        for succ in block.term.successors() {
+            for inst in &mut function.blocks[succ.0].insts {
+                if let Inst::Phi { args, .. } = inst {
+                    for (from, _) in args.iter_mut() {
+                        if from.0 == id {
+                            *from = pred;
+                        }
+                    }
+                }
+            }
+            for from in preds[succ.0].iter_mut() {
+                if from.0 == id {
+                    *from = pred;
+                }
+            }
+        }
+        let target = &mut function.blocks[pred.0];
+        target.insts.extend(block.insts);
+        target.term = block.term;
+        keep[id] = false;
+        changed = true;
+    }
+    if changed {
+        function.retain_blocks(&keep);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::tests::diamond;
+    use crate::ir::{BlockId, Terminator, VReg};
+    use crate::opt::simplify_cfg::run;
+
+    #[test]
+    fn merges_straight_line_blocks() {
+        // Once bb0 only jumps to bb1, the rest collapses into one block.
+        let mut function = diamond();
+        function.blocks[0].term = Terminator::Jump(BlockId(1));
+        assert!(run(&mut function));
+        assert_eq!(
+            function.to_string(),
+            "function f {\n\
+             bb0:\n  %0 = const 1\n  %1 = const 10\n  %4 = add %1, %0\n  ret %4\n\
+             }\n"
+        );
+        assert!(!run(&mut function));
+    }
+
+    #[test]
+    fn keeps_diamond() {
+        let mut function = diamond();
+        assert!(!run(&mut function));
+        function.blocks[0].term = Terminator::Branch {
+            cond: VReg(0),
+            then: BlockId(1),
+            els: BlockId(1),
+        };
+        assert!(run(&mut function));
+        assert_eq!(function.blocks.len(), 1);
+    }
+}
@@ -0,0 +1,645 @@
+use crate::ast::{BinOp, Expr, Program, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::token::{Token, TokenKind};
+
+/// Parsing stops once this many errors have been collected.
+pub const MAX_ERRORS: usize = 20;
+
+/// How deeply expressions may nest, both in parentheses and assignments
+/// while parsing and in the height of the tree built. Deeper ones would
+/// overflow the stack of the parser or of the passes that recurse over the
+/// tree. This is clang's default bracket depth, and fits in the 8 MiB stack
+/// of a main thread on Linux even in a debug build.
+pub const MAX_DEPTH: usize = 256;
+
+/// A recursive-descent parser over a token slice. The slice must end with
+/// an `Eof` token, as produced by `Token::parse`; the cursor never moves past
+/// it.
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    errors: Vec<Diagnostic>,
+    /// The parentheses and assignments the parser is inside of.
+    depth: usize,
+    /// The height of the expression parsed last.
+    height: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+            errors: vec![],
+            depth: 0,
+            height: 0,
+        }
+    }
+
+    /// Returns the token `n` tokens ahead of the cursor, or the final `Eof`.
+    fn peek_at(&self, n: usize) -> &'a Token {
+        let last = self.tokens.len() - 1;
+        &self.tokens[(self.position + n).min(last)]
+    }
+
+    fn peek(&self) -> &'a Token {
+        self.peek_at(0)
+    }
+
+    fn consume(&mut self) -> &'a Token {
+        let token = self.peek();
+        if !token.is_eof() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+        let span = lhs.span().to(rhs.span());
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span,
+        }
+    }
+
+    fn assignment(lhs: Expr, rhs: Expr) -> Expr {
+        let span = lhs.span().to(rhs.span());
+        Expr::Assign {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span,
+        }
+    }
+
+    /// Parses every statement of the program. A statement with a syntax
+    /// error is skipped so that the following ones are still checked, and all
+    /// collected errors are returned at once.
+    pub fn program(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        let mut nodes: Vec<Stmt> = vec![];
+        while !self.peek().is_eof() {
+            // A statement with an error leaves `depth` where it failed.
+            self.depth = 0;
+            match self.stmt() {
+                Ok(node) => nodes.push(node),
+                Err(error) => {
+                    self.errors.push(error);
+                    if self.errors.len() >= MAX_ERRORS {
+                        self.errors.push(Diagnostic::error(
+                            self.peek().span,
+                            "too many errors emitted, stopping now",
+                        ));
+                        break;
+                    }
+                    self.synchronize();
+                }
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(Program {
+                stmts: nodes,
+                locals: vec![],
+            })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Panic-mode recovery: discards tokens up to and including the next `;`
+    /// or `}`, where a new statement is likely to begin.
+    fn synchronize(&mut self) {
+        while !self.peek().is_eof() {
+            let token = self.consume();
+            if token.is_punct(";") || token.is_punct("}") {
+                break;
+            }
+        }
+    }
+
+    /// Enters the parentheses or the right-hand side of the assignment
+    /// opened at `span`. The caller leaves it by decrementing `depth`.
+    fn deeper(&mut self, span: Span) -> Result<(), Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            return Err(too_deep(span));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Records and returns the height of the node for the operator at
+    /// `span`, whose operands are the expression parsed last and one of
+    /// height `lhs`.
+    fn grow(&mut self, lhs: usize, span: Span) -> Result<usize, Diagnostic> {
+        self.height = lhs.max(self.height) + 1;
+        if self.height > MAX_DEPTH {
+            return Err(too_deep(span));
+        }
+        Ok(self.height)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<&'a Token, Diagnostic> {
+        if self.peek().is_punct(punct) {
+            Ok(self.consume())
+        } else {
+            Err(Diagnostic::error(
+                self.peek().span,
+                format!("expected '{}'", punct),
+            ))
+        }
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, Diagnostic> {
+        if self.peek().is_keyword("return") {
+            let keyword = self.consume().span;
+            let expr = self.expr()?;
+            self.expect(";")?;
+            let span = keyword.to(expr.span());
+            Ok(Stmt::Return { expr, span })
+        } else {
+            let expr = self.expr()?;
+            self.expect(";")?;
+            Ok(Stmt::Expr { expr })
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        self.assign()
+    }
+
+    fn assign(&mut self) -> Result<Expr, Diagnostic> {
+        let mut node = self.equality()?;
+        if self.peek().is_punct("=") {
+            let height = self.height;
+            let operator = self.consume().span;
+            self.deeper(operator)?;
+            let rhs = self.assign()?;
+            self.depth -= 1;
+            self.grow(height, operator)?;
+            node = Parser::assignment(node, rhs)
+        }
+        Ok(node)
+    }
+
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
+        let mut node = self.relational()?;
+        let mut height = self.height;
+
+        while let TokenKind::Punct(op) = self.peek().kind {
+            let operator = self.peek().span;
+            match op {
+                "==" => {
+                    self.consume();
+                    let rhs = self.relational()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Eq, node, rhs);
+                }
+                "!=" => {
+                    self.consume();
+                    let rhs = self.relational()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Ne, node, rhs);
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn relational(&mut self) -> Result<Expr, Diagnostic> {
+        let mut node = self.add()?;
+        let mut height = self.height;
+
+        while let TokenKind::Punct(op) = self.peek().kind {
+            let operator = self.peek().span;
+            match op {
+                "<" => {
+                    self.consume();
+                    let rhs = self.add()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Lt, node, rhs);
+                }
+                "<=" => {
+                    self.consume();
+                    let rhs = self.add()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Le, node, rhs);
+                }
+                ">" => {
+                    self.consume();
+                    let rhs = self.add()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Lt, rhs, node);
+                }
+                ">=" => {
+                    self.consume();
+                    let rhs = self.add()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Le, rhs, node);
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn add(&mut self) -> Result<Expr, Diagnostic> {
+        let mut node = self.mul()?;
+        let mut height = self.height;
+
+        while let TokenKind::Punct(op) = self.peek().kind {
+            let operator = self.peek().span;
+            match op {
+                "+" => {
+                    self.consume();
+                    let rhs = self.mul()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Add, node, rhs);
+                }
+                "-" => {
+                    self.consume();
+                    let rhs = self.mul()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Sub, node, rhs);
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn mul(&mut self) -> Result<Expr, Diagnostic> {
+        let mut node = self.unary()?;
+        let mut height = self.height;
+
+        while let TokenKind::Punct(op) = self.peek().kind {
+            let operator = self.peek().span;
+            match op {
+                "*" => {
+                    self.consume();
+                    let rhs = self.unary()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Mul, node, rhs);
+                }
+                "/" => {
+                    self.consume();
+                    let rhs = self.unary()?;
+                    height = self.grow(height, operator)?;
+                    node = Parser::binary(BinOp::Div, node, rhs);
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        match self.peek().kind {
+            TokenKind::Punct("+") => {
+                self.consume();
+                self.term()
+            }
+            TokenKind::Punct("-") => {
+                let span = self.consume().span;
+                let rhs = self.term()?;
+                self.grow(0, span)?;
+                let zero = Expr::Num { value: 0, span };
+                Ok(Parser::binary(BinOp::Sub, zero, rhs))
+            }
+            _ => self.term(),
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        match &self.peek().kind {
+            TokenKind::Punct("(") => {
+                let open = self.consume();
+                self.deeper(open.span)?;
+                let mut node = self.expr()?;
+                self.depth -= 1;
+                let close = match self.expect(")") {
+                    Ok(close) => close,
+                    Err(error) => return Err(error.with_label(open.span, "to match this '('")),
+                };
+                *node.span_mut() = open.span.to(close.span);
+                Ok(node)
+            }
+            TokenKind::Ident(ident) => {
+                let name = ident.clone();
+                let span = self.consume().span;
+                self.height = 1;
+                Ok(Expr::Var {
+                    name,
+                    id: None,
+                    span,
+                })
+            }
+            TokenKind::Str(string) => {
+                let value = string.clone();
+                let span = self.consume().span;
+                self.height = 1;
+                Ok(Expr::Str { value, span })
+            }
+            TokenKind::Num(num) => {
+                let value = *num;
+                let span = self.consume().span;
+                self.height = 1;
+                Ok(Expr::Num { value, span })
+            }
+            _ => Err(Diagnostic::error(self.peek().span, "expected expression")),
+        }
+    }
+}
+
+fn too_deep(span: Span) -> Diagnostic {
+    Diagnostic::error(span, "expression nested too deeply")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{BinOp, Expr, Stmt};
+    use crate::diagnostic::Diagnostic;
+    use crate::parser::{Parser, MAX_DEPTH, MAX_ERRORS};
+    use crate::span::{FileId, Span};
+    use crate::token::{Token, TokenKind};
+
+    fn lhs(expr: &Expr) -> &Expr {
+        match expr {
+            Expr::Binary { lhs, .. } | Expr::Assign { lhs, .. } => lhs,
+            _ => panic!("no lhs: {:?}", expr),
+        }
+    }
+
+    fn rhs(expr: &Expr) -> &Expr {
+        match expr {
+            Expr::Binary { rhs, .. } | Expr::Assign { rhs, .. } => rhs,
+            _ => panic!("no rhs: {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = Token::parse(FileId(0), "a = 1;\nreturn -(a + 2) >= 3;").unwrap();
+        let program = Parser::new(&tokens).program().unwrap().stmts;
+        let assignment = match &program[0] {
+            Stmt::Expr { expr } => expr,
+            stmt => panic!("not an expression: {:?}", stmt),
+        };
+        assert_eq!(assignment.span(), Span::new(FileId(0), 0, 5, 1, 1));
+
+        let comparison = match &program[1] {
+            Stmt::Return { expr, .. } => expr,
+            stmt => panic!("not a return: {:?}", stmt),
+        };
+        assert_eq!(comparison.span(), Span::new(FileId(0), 14, 13, 2, 8));
+        assert_eq!(program[1].span(), Span::new(FileId(0), 7, 20, 2, 1));
+
+        let negation = rhs(comparison);
+        assert_eq!(negation.span(), Span::new(FileId(0), 14, 8, 2, 8));
+        let parens = rhs(negation);
+        assert_eq!(parens.span(), Span::new(FileId(0), 15, 7, 2, 9));
+        let var = lhs(parens);
+        assert_eq!(var.span(), Span::new(FileId(0), 16, 1, 2, 10));
+    }
+
+    fn parse_errors(input: &str) -> Vec<Diagnostic> {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        Parser::new(&tokens).program().unwrap_err()
+    }
+
+    fn parse_error(input: &str) -> Diagnostic {
+        let mut errors = parse_errors(input);
+        assert_eq!(errors.len(), 1);
+        errors.remove(0)
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse_error("1 + 2");
+        assert_eq!(error.message, "expected ';'");
+        assert_eq!(error.span, Span::new(FileId(0), 5, 0, 1, 6));
+
+        let error = parse_error("a = 1 b;");
+        assert_eq!(error.message, "expected ';'");
+        assert_eq!(error.span, Span::new(FileId(0), 6, 1, 1, 7));
+
+        let error = parse_error("1 + ;");
+        assert_eq!(error.message, "expected expression");
+        assert_eq!(error.span, Span::new(FileId(0), 4, 1, 1, 5));
+
+        let error = parse_error("(1 + 2;");
+        assert_eq!(error.message, "expected ')'");
+        assert_eq!(error.span, Span::new(FileId(0), 6, 1, 1, 7));
+        assert_eq!(error.labels[0].span, Span::new(FileId(0), 0, 1, 1, 1));
+
+        let error = parse_error("(1 2);");
+        assert_eq!(error.message, "expected ')'");
+        assert_eq!(error.span, Span::new(FileId(0), 3, 1, 1, 4));
+
+        let error = parse_error("();");
+        assert_eq!(error.message, "expected expression");
+        assert_eq!(error.span, Span::new(FileId(0), 1, 1, 1, 2));
+    }
+
+    #[test]
+    fn recovery() {
+        let errors = parse_errors("a = 1 b; a = 2; (3 4) + 5; c = ; } return a;");
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[0].message, "expected ';'");
+        assert_eq!(errors[0].span.column, 7);
+        assert_eq!(errors[1].message, "expected ')'");
+        assert_eq!(errors[1].span.column, 20);
+        assert_eq!(errors[2].message, "expected expression");
+        assert_eq!(errors[2].span.column, 32);
+        assert_eq!(errors[3].message, "expected expression");
+        assert_eq!(errors[3].span.column, 34);
+    }
+
+    #[test]
+    fn error_limit() {
+        let input = "1 +;".repeat(MAX_ERRORS + 5);
+        let errors = parse_errors(&input);
+        assert_eq!(errors.len(), MAX_ERRORS + 1);
+        assert_eq!(
+            errors[MAX_ERRORS].message,
+            "too many errors emitted, stopping now"
+        );
+    }
+
+    fn parse(input: &str) -> Vec<Stmt> {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        Parser::new(&tokens).program().unwrap().stmts
+    }
+
+    /// Renders a statement with every operator fully parenthesized.
+    fn show(stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Expr { expr, .. } => show_expr(expr),
+            Stmt::Return { expr, .. } => format!("return {}", show_expr(expr)),
+        }
+    }
+
+    fn show_expr(expr: &Expr) -> String {
+        match expr {
+            Expr::Num { value, .. } => value.to_string(),
+            Expr::Str { value, .. } => format!("{:?}", value),
+            Expr::Var { name, .. } => name.clone(),
+            Expr::Binary { op, lhs, rhs, .. } => {
+                format!("({} {} {})", show_expr(lhs), op, show_expr(rhs))
+            }
+            Expr::Assign { lhs, rhs, .. } => {
+                format!("({} = {})", show_expr(lhs), show_expr(rhs))
+            }
+        }
+    }
+
+    #[test]
+    fn nested_parentheses() {
+        let cases = [
+            ("((1+2)*3);", "((1 + 2) * 3)"),
+            ("(a+(b));", "(a + b)"),
+            ("(1+(2*3))-4;", "((1 + (2 * 3)) - 4)"),
+            ("((((((7))))));", "7"),
+            ("(1)+(2)+(3);", "((1 + 2) + 3)"),
+            ("((1+2)*(3+4))/((5));", "(((1 + 2) * (3 + 4)) / 5)"),
+            ("-((1)-(2-(3-(4))));", "(0 - (1 - (2 - (3 - 4))))"),
+            ("((1 < (2)) == (3 >= ((4))));", "((1 < 2) == (4 <= 3))"),
+            ("a = ((b = (3)));", "(a = (b = 3))"),
+            ("return ((a));", "return a"),
+            ("1 < 2 + 3;", "(1 < (2 + 3))"),
+            ("1 + 2 >= 3 * 4;", "((3 * 4) <= (1 + 2))"),
+            ("(\"a\\n\") == b;", "(\"a\\n\" == b)"),
+        ];
+        for (input, expected) in cases.iter() {
+            let program = parse(input);
+            assert_eq!(show(&program[0]), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_parentheses() {
+        let depth = 64;
+        let input = format!("{}1{};", "(1+".repeat(depth), ")".repeat(depth));
+        let program = parse(&input);
+        let mut expr = match &program[0] {
+            Stmt::Expr { expr, .. } => expr,
+            stmt => panic!("not an expression: {:?}", stmt),
+        };
+        for _ in 0..depth {
+            match expr {
+                Expr::Binary {
+                    op: BinOp::Add,
+                    lhs,
+                    rhs,
+                    ..
+                } => {
+                    assert!(matches!(**lhs, Expr::Num { value: 1, .. }));
+                    expr = rhs;
+                }
+                _ => panic!("not an addition: {:?}", expr),
+            }
+        }
+        assert!(matches!(expr, Expr::Num { value: 1, .. }));
+    }
+
+    /// Runs `test` with the stack of a main thread, as the parser gets in
+    /// the compiler. Test threads have less.
+    fn with_main_stack(test: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn nesting_limit() {
+        with_main_stack(|| {
+            let parens = |depth| format!("{}1{};", "(".repeat(depth), ")".repeat(depth));
+            let chain = |length| format!("{}1;", "1 + ".repeat(length));
+            let assignments = |length| format!("{}1;", "a = ".repeat(length));
+            parse(&parens(MAX_DEPTH));
+            parse(&chain(MAX_DEPTH - 1));
+            parse(&assignments(MAX_DEPTH - 1));
+
+            // Each of these overflows the stack without the limit. The error
+            // points at the first parenthesis, assignment or operator that
+            // goes too deep.
+            let deep = 100_000;
+            let nested_chains = (0..200).fold("1".to_string(), |inner, _| {
+                format!("({}{})", inner, " + 1".repeat(deep / 200))
+            });
+            let cases = [
+                (parens(deep), 257),
+                (chain(deep), 1023),
+                (assignments(deep), 1027),
+                (format!("{};", nested_chains), 1223),
+                (format!("{}1;", "-(".repeat(deep)), 514),
+            ];
+            for (input, column) in cases.iter() {
+                let error = parse_error(input);
+                assert_eq!(error.message, "expression nested too deeply");
+                assert_eq!(error.span.column, *column, "{}", &input[..20]);
+            }
+        });
+    }
+
+    #[test]
+    fn cursor() {
+        let tokens = Token::parse(FileId(0), "a = 1;").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert!(parser.peek_at(1).is_punct("="));
+        assert!(parser.peek_at(10).is_eof());
+        assert!(parser.consume().kind == TokenKind::Ident("a".to_string()));
+        assert!(parser.expect("=").is_ok());
+        assert!(parser.expect(";").is_err());
+        parser.consume();
+        parser.consume();
+        assert!(parser.consume().is_eof());
+        assert!(parser.consume().is_eof());
+    }
+
+    /// Benchmark: parses generated programs of growing size and checks that
+    /// the time per statement stays flat. Run it with
+    /// `cargo test --release -- --ignored --nocapture bench_parser`.
+    #[test]
+    #[ignore]
+    fn bench_parser_scales_linearly() {
+        use std::time::Instant;
+
+        let statement = "abc = (abc + 12345) * 6 / 7 - -8;\nreturn abc >= 9;\n";
+        let mut per_statement = vec![];
+        for count in [25_000, 50_000, 100_000, 200_000].iter() {
+            let input = statement.repeat(*count);
+            let tokens = Token::parse(FileId(0), &input).unwrap();
+            let start = Instant::now();
+            let program = Parser::new(&tokens).program().unwrap().stmts;
+            let elapsed = start.elapsed();
+            let nanos = elapsed.as_nanos() as f64 / program.len() as f64;
+            println!(
+                "{:>7} statements in {:>10.3?} ({:.1} ns/statement)",
+                program.len(),
+                elapsed,
+                nanos
+            );
+            per_statement.push(nanos);
+        }
+        // A quadratic parser would take 8x longer per statement on the
+        // largest input.
+        assert!(
+            per_statement[3] < per_statement[0] * 3.0,
+            "{:?}",
+            per_statement
+        );
+    }
+}
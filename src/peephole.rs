@@ -0,0 +1,140 @@
+//! Peephole optimization of the generated x86 code. Every rule looks at an
+//! instruction together with the one kept just before it, and the rules are
+//! applied until none of them matches.
+
+use crate::x86::{Inst, Operand, Program};
+
+pub fn optimize(program: &mut Program) {
+    for function in &mut program.functions {
+        let mut insts = std::mem::take(&mut function.insts);
+        loop {
+            let (optimized, changed) = pass(insts);
+            insts = optimized;
+            if !changed {
+                break;
+            }
+        }
+        function.insts = insts;
+    }
+}
+
+fn pass(insts: Vec<Inst>) -> (Vec<Inst>, bool) {
+    let mut out: Vec<Inst> = Vec::with_capacity(insts.len());
+    let mut changed = false;
+    for inst in insts {
+        match (out.last(), &inst) {
+            // mov rax, rax
+            (_, Inst::Mov(dst, src)) if dst == src => {}
+            // push rax; pop rax, or push 3; pop rdi into mov rdi, 3
+            (Some(Inst::Push(src)), Inst::Pop(dst)) => {
+                let src = src.clone();
+                out.pop();
+                if src != Operand::Reg(*dst) {
+                    out.push(Inst::Mov(Operand::Reg(*dst), src));
+                }
+            }
+            // mov [rbp - 8], rax; mov rax, [rbp - 8]
+            (Some(Inst::Mov(a, b)), Inst::Mov(c, d)) if a == d && b == c => {}
+            // jmp .L1; .L1:
+            (Some(Inst::Jmp(target)), Inst::Label(label)) if target == label => {
+                out.pop();
+                out.push(inst);
+            }
+            _ => {
+                out.push(inst);
+                continue;
+            }
+        }
+        changed = true;
+    }
+    (out, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::peephole::optimize;
+    use crate::x86::{Function, Inst, Operand, Program, Reg};
+
+    fn optimized(insts: Vec<Inst>) -> Vec<Inst> {
+        let mut program = Program {
+            data: vec![],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts,
+            }],
+        };
+        optimize(&mut program);
+        program.functions.remove(0).insts
+    }
+
+    fn reg(reg: Reg) -> Operand {
+        Operand::Reg(reg)
+    }
+
+    #[test]
+    fn push_pop() {
+        assert_eq!(
+            optimized(vec![
+                Inst::Push(reg(Reg::Rax)),
+                Inst::Pop(Reg::Rax),
+                Inst::Push(Operand::Imm(3)),
+                Inst::Pop(Reg::Rdi),
+                Inst::Ret,
+            ]),
+            vec![Inst::Mov(reg(Reg::Rdi), Operand::Imm(3)), Inst::Ret]
+        );
+    }
+
+    #[test]
+    fn nested_push_pop() {
+        // Removing the inner pair exposes the outer one.
+        assert_eq!(
+            optimized(vec![
+                Inst::Push(reg(Reg::Rax)),
+                Inst::Push(reg(Reg::Rdi)),
+                Inst::Pop(Reg::Rdi),
+                Inst::Pop(Reg::Rsi),
+            ]),
+            vec![Inst::Mov(reg(Reg::Rsi), reg(Reg::Rax))]
+        );
+    }
+
+    #[test]
+    fn redundant_moves() {
+        let slot = Operand::Mem {
+            base: Reg::Rbp,
+            disp: -8,
+        };
+        assert_eq!(
+            optimized(vec![
+                Inst::Mov(reg(Reg::Rbx), reg(Reg::Rbx)),
+                Inst::Mov(slot.clone(), reg(Reg::Rax)),
+                Inst::Mov(reg(Reg::Rax), slot.clone()),
+                Inst::Mov(reg(Reg::Rdi), slot.clone()),
+            ]),
+            vec![
+                Inst::Mov(slot.clone(), reg(Reg::Rax)),
+                Inst::Mov(reg(Reg::Rdi), slot),
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_to_next_instruction() {
+        assert_eq!(
+            optimized(vec![
+                Inst::Jmp(".L1".to_string()),
+                Inst::Label(".L1".to_string()),
+                Inst::Jmp(".L3".to_string()),
+                Inst::Label(".L2".to_string()),
+                Inst::Label(".L3".to_string()),
+            ]),
+            vec![
+                Inst::Label(".L1".to_string()),
+                Inst::Jmp(".L3".to_string()),
+                Inst::Label(".L2".to_string()),
+                Inst::Label(".L3".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Reachability over the control-flow graph. Lowered code is pruned before
+//! it is optimized, so that no optimization level emits a block the entry
+//! cannot reach, such as the statements after a `return`.
+
+use crate::diagnostic::Diagnostic;
+use crate::ir::Function;
+
+/// Deletes the blocks of `function` that cannot be reached from its entry
+/// and returns a `-Wunreachable-code` warning for each stretch of them.
+/// Lowering lays blocks out in source order, so consecutive unreachable
+/// blocks are one stretch of dead code and only the first is reported.
+pub fn remove_unreachable_code(function: &mut Function) -> Vec<Diagnostic> {
+    let reachable = function.reachable();
+    let mut warnings = vec![];
+    for (id, block) in function.blocks.iter().enumerate() {
+        if reachable[id] || !reachable[id - 1] {
+            continue;
+        }
+        if let Some(span) = block.span {
+            warnings.push(Diagnostic::warning(
+                span,
+                "code will never be executed [-Wunreachable-code]",
+            ));
+        }
+    }
+    function.retain_blocks(&reachable);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::Diagnostic;
+    use crate::ir::Function;
+    use crate::lower::lower;
+    use crate::parser::Parser;
+    use crate::reach::remove_unreachable_code;
+    use crate::sema;
+    use crate::span::FileId;
+    use crate::token::Token;
+
+    fn pruned(input: &str) -> (Function, Vec<Diagnostic>) {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        let mut program = Parser::new(&tokens).program().unwrap();
+        sema::analyze(&mut program).unwrap();
+        let mut function = lower(&program).functions.remove(0);
+        let warnings = remove_unreachable_code(&mut function);
+        (function, warnings)
+    }
+
+    #[test]
+    fn removes_statements_after_return() {
+        let (function, warnings) = pruned("a = 1; return a; a = 2; return 3; a + 4;");
+        assert_eq!(
+            function.to_string(),
+            "function main {\n\
+             bb0:\n  %0 = const 1\n  store $a, %0\n  %1 = load $a\n  jmp bb1\n\
+             bb1:\n  %7 = phi [bb0: %1]\n  ret %7\n\
+             }\n"
+        );
+        // The two dead blocks are reported once, at the first statement.
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "code will never be executed [-Wunreachable-code]"
+        );
+        assert_eq!((warnings[0].span.column, warnings[0].span.len), (18, 5));
+    }
+
+    #[test]
+    fn keeps_reachable_code() {
+        let (function, warnings) = pruned("a = 1; a + 2; return a;");
+        assert_eq!(function.blocks.len(), 2);
+        assert!(warnings.is_empty());
+    }
+}
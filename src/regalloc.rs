@@ -0,0 +1,292 @@
+//! Linear-scan register allocation (Poletto and Sarkar). Each virtual
+//! register gets one live interval over the blocks laid out in order, and
+//! intervals are assigned registers in order of their start. When none is
+//! free, the interval that ends last is spilled to the stack.
+//!
+//! Only virtual registers are allocated. Local variables live in stack
+//! slots until `ssa` promotes them, which the pass manager does from -O1,
+//! so at -O0 every access to a local still goes through memory.
+
+use crate::ir::{Function, VReg};
+use crate::x86::Reg;
+use std::collections::HashSet;
+
+/// Registers in order of preference. `rax`, `rcx` and `rdx` are left out:
+/// the code generator uses them as scratch registers, division clobbers
+/// `rax` and `rdx`, and shifts take their count in `cl`. Caller-saved
+/// registers come first because using them costs nothing; the callee-saved
+/// ones must be saved in the prologue and restored before returning.
+const REGS: [Reg; 11] = [
+    Reg::Rsi,
+    Reg::Rdi,
+    Reg::R8,
+    Reg::R9,
+    Reg::R10,
+    Reg::R11,
+    Reg::Rbx,
+    Reg::R12,
+    Reg::R13,
+    Reg::R14,
+    Reg::R15,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Reg(Reg),
+    /// The index of a spill slot.
+    Spill(usize),
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    /// Where each virtual register lives. Registers that are never defined
+    /// or used get `None`.
+    pub locations: Vec<Option<Location>>,
+    pub spill_slots: usize,
+    /// The callee-saved registers handed out, which the function must
+    /// preserve.
+    pub callee_saved: Vec<Reg>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    reg: VReg,
+    start: usize,
+    end: usize,
+}
+
+/// Allocates registers for `function`, which must not contain phis.
+pub fn allocate(function: &Function) -> Allocation {
+    allocate_with(function, &REGS)
+}
+
+fn allocate_with(function: &Function, regs: &[Reg]) -> Allocation {
+    let mut intervals = live_intervals(function);
+    intervals.sort_by_key(|interval| (interval.start, interval.reg));
+
+    let mut allocation = Allocation {
+        locations: vec![None; function.vreg_count as usize],
+        spill_slots: 0,
+        callee_saved: vec![],
+    };
+    let mut free = vec![true; regs.len()];
+    // Intervals holding a register, as (interval, index into `regs`).
+    let mut active: Vec<(Interval, usize)> = vec![];
+    for interval in intervals {
+        // An operand read by the instruction that defines `interval` can
+        // share its register, since every operand is read before the result
+        // is written.
+        active.retain(|(other, index)| {
+            if other.end <= interval.start {
+                free[*index] = true;
+                false
+            } else {
+                true
+            }
+        });
+        let location = match free.iter().position(|free| *free) {
+            Some(index) => {
+                free[index] = false;
+                active.push((interval, index));
+                Location::Reg(regs[index])
+            }
+            None => {
+                let victim = (0..active.len()).max_by_key(|i| active[*i].0.end).unwrap();
+                let (other, index) = active[victim];
+                if other.end > interval.end {
+                    active.remove(victim);
+                    allocation.locations[other.reg.0 as usize] =
+                        Some(Location::Spill(allocation.spill_slots));
+                    allocation.spill_slots += 1;
+                    active.push((interval, index));
+                    Location::Reg(regs[index])
+                } else {
+                    allocation.spill_slots += 1;
+                    Location::Spill(allocation.spill_slots - 1)
+                }
+            }
+        };
+        allocation.locations[interval.reg.0 as usize] = Some(location);
+    }
+
+    for location in allocation.locations.iter().flatten() {
+        if let Location::Reg(reg) = location {
+            if reg.is_callee_saved() && !allocation.callee_saved.contains(reg) {
+                allocation.callee_saved.push(*reg);
+            }
+        }
+    }
+    allocation
+}
+
+/// Computes one interval per register over the instructions numbered in
+/// block order, each terminator included. A register live into or out of a
+/// block covers the start or the end of that block.
+fn live_intervals(function: &Function) -> Vec<Interval> {
+    let count = function.blocks.len();
+    let mut uses: Vec<HashSet<VReg>> = vec![HashSet::new(); count];
+    let mut defs: Vec<HashSet<VReg>> = vec![HashSet::new(); count];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for inst in &block.insts {
+            for reg in inst.uses() {
+                if !defs[id].contains(&reg) {
+                    uses[id].insert(reg);
+                }
+            }
+            if let Some(dst) = inst.dst() {
+                defs[id].insert(dst);
+            }
+        }
+        for reg in block.term.uses() {
+            if !defs[id].contains(&reg) {
+                uses[id].insert(reg);
+            }
+        }
+    }
+
+    let mut live_in: Vec<HashSet<VReg>> = vec![HashSet::new(); count];
+    let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); count];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..count).rev() {
+            let mut out = HashSet::new();
+            for succ in function.blocks[id].term.successors() {
+                out.extend(live_in[succ.0].iter().copied());
+            }
+            let mut live = uses[id].clone();
+            live.extend(out.difference(&defs[id]).copied());
+            if live != live_in[id] || out != live_out[id] {
+                live_in[id] = live;
+                live_out[id] = out;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; function.vreg_count as usize];
+    let mut cover = |reg: VReg, position: usize| {
+        let range = &mut ranges[reg.0 as usize];
+        *range = match *range {
+            Some((start, end)) => Some((start.min(position), end.max(position))),
+            None => Some((position, position)),
+        };
+    };
+    let mut position = 0;
+    for (id, block) in function.blocks.iter().enumerate() {
+        let start = position;
+        for reg in &live_in[id] {
+            cover(*reg, start);
+        }
+        for inst in &block.insts {
+            for reg in inst.uses() {
+                cover(reg, position);
+            }
+            if let Some(dst) = inst.dst() {
+                cover(dst, position);
+            }
+            position += 1;
+        }
+        for reg in block.term.uses() {
+            cover(reg, position);
+        }
+        for reg in &live_out[id] {
+            cover(*reg, position);
+        }
+        position += 1;
+    }
+
+    ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(reg, range)| {
+            range.map(|(start, end)| Interval {
+                reg: VReg(reg as u32),
+                start,
+                end,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::ast::BinOp;
+    use crate::ir::tests::diamond;
+    use crate::ir::{Block, Function, Inst, Terminator, VReg};
+    use crate::regalloc::{allocate, allocate_with, Location};
+    use crate::x86::Reg;
+
+    #[test]
+    fn reuses_registers() {
+        // %0 lives through the whole diamond. The copy of the phi, %5, can
+        // take the register of %1, which dies where %5 is defined.
+        let mut function = diamond();
+        function.eliminate_phis();
+        let allocation = allocate(&function);
+        assert_eq!(allocation.spill_slots, 0);
+        assert!(allocation.callee_saved.is_empty());
+        let location = |reg: u32| allocation.locations[reg as usize].unwrap();
+        assert_eq!(location(0), Location::Reg(Reg::Rsi));
+        assert_eq!(location(5), location(1));
+        assert_ne!(location(2), location(0));
+        assert_ne!(location(2), location(5));
+    }
+
+    /// A block that defines `count` constants and then adds them all up, so
+    /// they are all live at once.
+    pub fn pressure(count: u32) -> Function {
+        let mut insts = vec![];
+        for reg in 0..count {
+            insts.push(Inst::Const {
+                dst: VReg(reg),
+                value: reg as i64,
+            });
+        }
+        let mut sum = VReg(0);
+        for reg in 1..count {
+            insts.push(Inst::Binary {
+                dst: VReg(count + reg),
+                op: BinOp::Add,
+                lhs: sum,
+                rhs: VReg(reg),
+            });
+            sum = VReg(count + reg);
+        }
+        Function {
+            name: "f".to_string(),
+            blocks: vec![Block {
+                insts,
+                term: Terminator::Ret(sum),
+                span: None,
+            }],
+            slots: vec![],
+            vreg_count: 2 * count,
+        }
+    }
+
+    #[test]
+    fn spills_longest_intervals() {
+        let regs = [Reg::Rbx, Reg::R12, Reg::R13];
+        // %3 ends after the other constants, so it goes to the stack.
+        let mut function = pressure(4);
+        let allocation = allocate_with(&function, &regs);
+        assert_eq!(allocation.spill_slots, 1);
+        assert_eq!(allocation.locations[3], Some(Location::Spill(0)));
+        assert_eq!(allocation.callee_saved, regs.to_vec());
+
+        // Once %0 is also returned, it is the one ending last.
+        function.blocks[0].term = Terminator::Ret(VReg(0));
+        let allocation = allocate_with(&function, &regs);
+        assert_eq!(allocation.spill_slots, 1);
+        assert_eq!(allocation.locations[0], Some(Location::Spill(0)));
+        assert_eq!(allocation.locations[3], Some(Location::Reg(Reg::Rbx)));
+    }
+
+    #[test]
+    fn spills_under_pressure() {
+        let allocation = allocate(&pressure(20));
+        assert_eq!(allocation.spill_slots, 20 - 11);
+        assert_eq!(allocation.callee_saved.len(), 5);
+    }
+}
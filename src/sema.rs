@@ -0,0 +1,304 @@
+use crate::ast::{BinOp, Expr, LVar, Program, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::types::Type;
+
+/// Resolves every variable reference of `program` to an entry of
+/// `program.locals` and checks the types of all expressions.
+///
+/// A variable is declared by the first assignment to it, so reading a name
+/// before it has been assigned is an error.
+pub fn analyze(program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    let mut checker = Checker {
+        locals: vec![],
+        errors: vec![],
+    };
+    for stmt in &mut program.stmts {
+        checker.stmt(stmt);
+    }
+    program.locals = checker.locals;
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+struct Checker {
+    locals: Vec<LVar>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.locals.iter().position(|lvar| lvar.name == name)
+    }
+
+    fn declare(&mut self, name: &str, ty: Type, span: Span) -> usize {
+        self.locals.push(LVar {
+            name: name.to_string(),
+            ty,
+            span,
+            offset: 0,
+        });
+        self.locals.len() - 1
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Expr { expr } => {
+                self.expr(expr);
+            }
+            Stmt::Return { expr, .. } => {
+                if let Some(ty) = self.expr(expr) {
+                    if !ty.is_integer() {
+                        self.errors.push(Diagnostic::error(
+                            expr.span(),
+                            format!(
+                                "incompatible types when returning type '{}' but 'int' was expected",
+                                ty
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the type of `expr`, or `None` if an error has been reported
+    /// for it. Enclosing expressions stay quiet about `None` operands so that
+    /// one mistake is reported only once.
+    fn expr(&mut self, expr: &mut Expr) -> Option<Type> {
+        match expr {
+            Expr::Num { .. } => Some(Type::Int),
+            Expr::Str { .. } => Some(Type::pointer_to(Type::Char)),
+            Expr::Var { name, id, span } => match self.lookup(name) {
+                Some(index) => {
+                    *id = Some(index);
+                    Some(self.locals[index].ty.clone())
+                }
+                None => {
+                    self.errors.push(Diagnostic::error(
+                        *span,
+                        format!("use of undeclared identifier '{}'", name),
+                    ));
+                    None
+                }
+            },
+            Expr::Assign { lhs, rhs, .. } => {
+                let rhs_ty = self.expr(rhs);
+                self.assign(lhs, rhs_ty)
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let lhs_ty = self.expr(lhs);
+                let rhs_ty = self.expr(rhs);
+                let (lhs_ty, rhs_ty) = (lhs_ty?, rhs_ty?);
+                let ty = binary_type(*op, &lhs_ty, &rhs_ty);
+                if ty.is_none() {
+                    let comparison = matches!(op, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le);
+                    let message = if comparison && lhs_ty.is_pointer() != rhs_ty.is_pointer() {
+                        "comparison between pointer and integer".to_string()
+                    } else {
+                        format!(
+                            "invalid operands to binary {} (have '{}' and '{}')",
+                            op, lhs_ty, rhs_ty
+                        )
+                    };
+                    self.errors.push(Diagnostic::error(*span, message));
+                }
+                ty
+            }
+        }
+    }
+
+    /// Checks an assignment of a value of type `rhs_ty` to `lhs`. Assigning
+    /// to an undeclared name declares it.
+    fn assign(&mut self, lhs: &mut Expr, rhs_ty: Option<Type>) -> Option<Type> {
+        let (name, id, span) = match lhs {
+            Expr::Var { name, id, span } => (name, id, *span),
+            _ => {
+                self.expr(lhs);
+                self.errors.push(
+                    Diagnostic::error(lhs.span(), "lvalue required as left operand of assignment")
+                        .with_note("only variables can be assigned to"),
+                );
+                return None;
+            }
+        };
+        match self.lookup(name) {
+            Some(index) => {
+                *id = Some(index);
+                let lvar = &self.locals[index];
+                let rhs_ty = rhs_ty?;
+                if rhs_ty != lvar.ty {
+                    self.errors.push(
+                        Diagnostic::error(
+                            span,
+                            format!(
+                                "incompatible types when assigning to type '{}' from type '{}'",
+                                lvar.ty, rhs_ty
+                            ),
+                        )
+                        .with_label(
+                            lvar.span,
+                            format!("'{}' declared here with type '{}'", lvar.name, lvar.ty),
+                        ),
+                    );
+                }
+                Some(lvar.ty.clone())
+            }
+            None => {
+                // Declare the variable even if its initializer is broken, so
+                // that later uses are not reported as undeclared.
+                let ty = rhs_ty.clone().unwrap_or(Type::Int);
+                *id = Some(self.declare(name, ty, span));
+                rhs_ty
+            }
+        }
+    }
+}
+
+/// Returns the type of `lhs op rhs`, or `None` if the operands are invalid.
+/// Only `char` pointers exist, so pointer arithmetic needs no scaling.
+fn binary_type(op: BinOp, lhs: &Type, rhs: &Type) -> Option<Type> {
+    match op {
+        BinOp::Add => match (lhs.is_pointer(), rhs.is_pointer()) {
+            (false, false) => Some(Type::Int),
+            (true, false) => Some(lhs.clone()),
+            (false, true) => Some(rhs.clone()),
+            (true, true) => None,
+        },
+        BinOp::Sub => match (lhs.is_pointer(), rhs.is_pointer()) {
+            (false, false) => Some(Type::Int),
+            (true, false) => Some(lhs.clone()),
+            (true, true) if lhs == rhs => Some(Type::Int),
+            _ => None,
+        },
+        BinOp::Mul | BinOp::Div | BinOp::Shl => {
+            if lhs.is_integer() && rhs.is_integer() {
+                Some(Type::Int)
+            } else {
+                None
+            }
+        }
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le => {
+            if (lhs.is_integer() && rhs.is_integer()) || lhs == rhs {
+                Some(Type::Int)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Expr, Program, Stmt};
+    use crate::diagnostic::Diagnostic;
+    use crate::parser::Parser;
+    use crate::sema::analyze;
+    use crate::span::FileId;
+    use crate::token::Token;
+    use crate::types::Type;
+
+    fn parse(input: &str) -> Program {
+        let tokens = Token::parse(FileId(0), input).unwrap();
+        Parser::new(&tokens).program().unwrap()
+    }
+
+    fn errors(input: &str) -> Vec<Diagnostic> {
+        analyze(&mut parse(input)).unwrap_err()
+    }
+
+    #[test]
+    fn resolves_variables() {
+        let mut program = parse("a = 1; b = \"s\"; a = a + 2; return a;");
+        analyze(&mut program).unwrap();
+        assert_eq!(program.locals.len(), 2);
+        assert_eq!(program.locals[0].name, "a");
+        assert_eq!(program.locals[0].ty, Type::Int);
+        assert_eq!(program.locals[1].name, "b");
+        assert_eq!(program.locals[1].ty, Type::pointer_to(Type::Char));
+        match &program.stmts[3] {
+            Stmt::Return {
+                expr: Expr::Var { id, .. },
+                ..
+            } => assert_eq!(*id, Some(0)),
+            stmt => panic!("unexpected statement: {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn pointer_arithmetic() {
+        let mut program = parse("s = \"abc\"; t = s + 1; t = 1 + t; n = t - s; return n == 2;");
+        analyze(&mut program).unwrap();
+        assert_eq!(program.locals[1].ty, Type::pointer_to(Type::Char));
+        assert_eq!(program.locals[2].ty, Type::Int);
+    }
+
+    #[test]
+    fn undeclared_identifier() {
+        let errors = errors("a = 1; b + a; c = c;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "use of undeclared identifier 'b'");
+        assert_eq!(errors[0].span.column, 8);
+        assert_eq!(errors[1].message, "use of undeclared identifier 'c'");
+        assert_eq!(errors[1].span.column, 19);
+    }
+
+    #[test]
+    fn non_lvalue() {
+        let errors = errors("1 = 2; a = 1; a + 1 = 3;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].message,
+            "lvalue required as left operand of assignment"
+        );
+        assert_eq!(errors[0].span.column, 1);
+        assert_eq!(errors[1].span.column, 15);
+        assert_eq!(errors[1].span.len, 5);
+    }
+
+    #[test]
+    fn incompatible_operands() {
+        let errors = errors("s = \"a\"; s * 2; s + s; s == 1; 1 - s; return s;");
+        assert_eq!(errors.len(), 5);
+        assert_eq!(
+            errors[0].message,
+            "invalid operands to binary * (have 'char *' and 'int')"
+        );
+        assert_eq!(
+            errors[1].message,
+            "invalid operands to binary + (have 'char *' and 'char *')"
+        );
+        assert_eq!(errors[2].message, "comparison between pointer and integer");
+        assert_eq!(
+            errors[3].message,
+            "invalid operands to binary - (have 'int' and 'char *')"
+        );
+        assert_eq!(
+            errors[4].message,
+            "incompatible types when returning type 'char *' but 'int' was expected"
+        );
+    }
+
+    #[test]
+    fn incompatible_assignment() {
+        let errors = errors("a = 1;\na = \"x\";");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "incompatible types when assigning to type 'int' from type 'char *'"
+        );
+        assert_eq!(errors[0].span.line, 2);
+        assert_eq!(errors[0].labels[0].span.line, 1);
+    }
+
+    #[test]
+    fn reports_each_error_once() {
+        let errors = errors("a = b + 1; a * 2; c = (d = 1) + e;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "use of undeclared identifier 'b'");
+        assert_eq!(errors[1].message, "use of undeclared identifier 'e'");
+    }
+}
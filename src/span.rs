@@ -0,0 +1,110 @@
+/// Identifies one source file of a compilation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileId(pub usize);
+
+/// A contiguous range of source text. `line` and `column` are 1-based and
+/// describe the first byte of the range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, offset: usize, len: usize, line: usize, column: usize) -> Self {
+        Span {
+            file,
+            offset,
+            len,
+            line,
+            column,
+        }
+    }
+
+    /// Returns the byte offset just past the end of the span.
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    /// Returns the smallest span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        let first = if self.offset <= other.offset {
+            *self
+        } else {
+            other
+        };
+        let end = self.end().max(other.end());
+        Span {
+            len: end - first.offset,
+            ..first
+        }
+    }
+}
+
+/// A source file registered with a `SourceMap`.
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+impl SourceFile {
+    /// Returns the text of the 1-based line `line`, without its line break.
+    pub fn line(&self, line: usize) -> &str {
+        self.text
+            .split('\n')
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r')
+    }
+}
+
+/// Owns every source file of a compilation and hands out their `FileId`s.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: vec![] }
+    }
+
+    pub fn add(&mut self, name: String, text: String) -> FileId {
+        self.files.push(SourceFile { name, text });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::span::{FileId, SourceMap, Span};
+
+    #[test]
+    fn to() {
+        let lhs = Span::new(FileId(0), 2, 1, 1, 3);
+        let rhs = Span::new(FileId(0), 6, 2, 1, 7);
+        assert_eq!(lhs.to(rhs), Span::new(FileId(0), 2, 6, 1, 3));
+        assert_eq!(lhs.to(rhs).end(), 8);
+        assert_eq!(rhs.to(lhs), Span::new(FileId(0), 2, 6, 1, 3));
+    }
+
+    #[test]
+    fn source_map() {
+        let mut sources = SourceMap::new();
+        let first = sources.add("a.c".to_string(), "1;".to_string());
+        let second = sources.add("b.c".to_string(), "a = 1;\r\nb;\n".to_string());
+        assert_eq!(first, FileId(0));
+        assert_eq!(second, FileId(1));
+        assert_eq!(sources.get(second).name, "b.c");
+        assert_eq!(sources.get(second).line(1), "a = 1;");
+        assert_eq!(sources.get(second).line(2), "b;");
+        assert_eq!(sources.get(second).line(5), "");
+    }
+}
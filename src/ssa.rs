@@ -0,0 +1,306 @@
+use crate::ir::{BlockId, Function, Inst, SlotId, VReg};
+use std::collections::HashMap;
+
+/// The dominator tree of the blocks reachable from the entry, computed with
+/// the iterative algorithm of Cooper, Harvey and Kennedy.
+pub struct Dominators {
+    /// Immediate dominator of each block. The entry is its own immediate
+    /// dominator; unreachable blocks have none.
+    idom: Vec<Option<BlockId>>,
+    /// Position of each reachable block in reverse postorder.
+    order: Vec<usize>,
+}
+
+impl Dominators {
+    pub fn new(function: &Function) -> Dominators {
+        let rpo = function.reverse_postorder();
+        let preds = function.predecessors();
+        let mut order = vec![usize::MAX; function.blocks.len()];
+        for (i, block) in rpo.iter().enumerate() {
+            order[block.0] = i;
+        }
+        let mut dominators = Dominators {
+            idom: vec![None; function.blocks.len()],
+            order,
+        };
+        dominators.idom[0] = Some(BlockId(0));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &preds[block.0] {
+                    if dominators.idom[pred.0].is_none() {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        None => Some(*pred),
+                        Some(other) => Some(dominators.intersect(*pred, other)),
+                    };
+                }
+                if dominators.idom[block.0] != new_idom {
+                    dominators.idom[block.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        dominators
+    }
+
+    fn intersect(&self, mut a: BlockId, mut b: BlockId) -> BlockId {
+        while a != b {
+            while self.order[a.0] > self.order[b.0] {
+                a = self.idom[a.0].unwrap();
+            }
+            while self.order[b.0] > self.order[a.0] {
+                b = self.idom[b.0].unwrap();
+            }
+        }
+        a
+    }
+
+    /// The immediate dominator of `block`, or `None` for the entry and for
+    /// unreachable blocks.
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        match self.idom[block.0] {
+            Some(idom) if idom != block => Some(idom),
+            _ => None,
+        }
+    }
+
+    /// The blocks immediately dominated by each block.
+    pub fn children(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![vec![]; self.idom.len()];
+        for block in 0..self.idom.len() {
+            if let Some(idom) = self.idom(BlockId(block)) {
+                children[idom.0].push(BlockId(block));
+            }
+        }
+        children
+    }
+
+    /// The dominance frontier of each block: the blocks where its dominance
+    /// ends, which is where definitions in it may meet others.
+    pub fn frontiers(&self, function: &Function) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![vec![]; function.blocks.len()];
+        for (block, preds) in function.predecessors().iter().enumerate() {
+            let idom = match self.idom[block] {
+                Some(idom) => idom,
+                None => continue,
+            };
+            if preds.len() < 2 {
+                continue;
+            }
+            for pred in preds {
+                if self.idom[pred.0].is_none() {
+                    continue;
+                }
+                let mut runner = *pred;
+                while runner != idom {
+                    if !frontiers[runner.0].contains(&BlockId(block)) {
+                        frontiers[runner.0].push(BlockId(block));
+                    }
+                    runner = self.idom[runner.0].unwrap();
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+/// Puts `function` into SSA form by promoting every stack slot to virtual
+/// registers: loads and stores disappear, and phis are inserted on the
+/// iterated dominance frontiers of the stores. Unreachable blocks are
+/// deleted first. Returns whether anything changed.
+pub fn construct(function: &mut Function) -> bool {
+    let removed = function.remove_unreachable_blocks();
+    let has_memory = function.blocks.iter().any(|block| {
+        block
+            .insts
+            .iter()
+            .any(|inst| matches!(inst, Inst::Load { .. } | Inst::Store { .. }))
+    });
+    if !has_memory {
+        return removed;
+    }
+
+    let dominators = Dominators::new(function);
+    let frontiers = dominators.frontiers(function);
+    let mut phis: Vec<Vec<(SlotId, VReg)>> = vec![vec![]; function.blocks.len()];
+    for slot in 0..function.slots.len() {
+        let mut worklist: Vec<BlockId> = vec![];
+        for (id, block) in function.blocks.iter().enumerate() {
+            let stores = block
+                .insts
+                .iter()
+                .any(|inst| matches!(inst, Inst::Store { slot: s, .. } if s.0 == slot));
+            if stores {
+                worklist.push(BlockId(id));
+            }
+        }
+        while let Some(block) = worklist.pop() {
+            for frontier in &frontiers[block.0] {
+                if phis[frontier.0].iter().all(|(s, _)| s.0 != slot) {
+                    let dst = function.new_vreg();
+                    phis[frontier.0].push((SlotId(slot), dst));
+                    worklist.push(*frontier);
+                }
+            }
+        }
+    }
+
+    // Reading a slot that has not been stored to on some path yields zero.
+    let undef = function.new_vreg();
+    function.blocks[0].insts.insert(
+        0,
+        Inst::Const {
+            dst: undef,
+            value: 0,
+        },
+    );
+
+    let mut renamer = Renamer {
+        children: dominators.children(),
+        phis,
+        phi_args: HashMap::new(),
+        replacements: HashMap::new(),
+    };
+    let current = vec![undef; function.slots.len()];
+    renamer.rename(function, BlockId(0), current);
+
+    for (id, block_phis) in renamer.phis.iter().enumerate() {
+        let insts = &mut function.blocks[id].insts;
+        for (_, dst) in block_phis.iter().rev() {
+            let args = renamer.phi_args.remove(dst).unwrap_or_default();
+            insts.insert(0, Inst::Phi { dst: *dst, args });
+        }
+    }
+    true
+}
+
+struct Renamer {
+    children: Vec<Vec<BlockId>>,
+    /// The phis inserted at the start of each block, by the slot they merge.
+    phis: Vec<Vec<(SlotId, VReg)>>,
+    phi_args: HashMap<VReg, Vec<(BlockId, VReg)>>,
+    /// The value each removed load read.
+    replacements: HashMap<VReg, VReg>,
+}
+
+impl Renamer {
+    /// Walks the dominator tree from `block`, where `current` holds the
+    /// value each slot has on entry.
+    fn rename(&mut self, function: &mut Function, block: BlockId, mut current: Vec<VReg>) {
+        for (slot, dst) in &self.phis[block.0] {
+            current[slot.0] = *dst;
+        }
+        let insts = std::mem::take(&mut function.blocks[block.0].insts);
+        for mut inst in insts {
+            for reg in inst.uses_mut() {
+                if let Some(replacement) = self.replacements.get(reg) {
+                    *reg = *replacement;
+                }
+            }
+            match inst {
+                Inst::Load { dst, slot } => {
+                    self.replacements.insert(dst, current[slot.0]);
+                }
+                Inst::Store { slot, src } => current[slot.0] = src,
+                inst => function.blocks[block.0].insts.push(inst),
+            }
+        }
+        for reg in function.blocks[block.0].term.uses_mut() {
+            if let Some(replacement) = self.replacements.get(reg) {
+                *reg = *replacement;
+            }
+        }
+
+        for succ in function.blocks[block.0].term.successors() {
+            for (slot, dst) in &self.phis[succ.0] {
+                let args = self.phi_args.entry(*dst).or_default();
+                if args.iter().all(|(pred, _)| *pred != block) {
+                    args.push((block, current[slot.0]));
+                }
+            }
+            for inst in &mut function.blocks[succ.0].insts {
+                if let Inst::Phi { args, .. } = inst {
+                    for (pred, value) in args.iter_mut() {
+                        if *pred == block {
+                            if let Some(replacement) = self.replacements.get(value) {
+                                *value = *replacement;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for child in self.children[block.0].clone() {
+            self.rename(function, child, current.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::tests::diamond;
+    use crate::ir::{BlockId, Inst, Slot, SlotId, Terminator, VReg};
+    use crate::ssa::{construct, Dominators};
+
+    #[test]
+    fn dominators() {
+        let function = diamond();
+        let dominators = Dominators::new(&function);
+        assert_eq!(dominators.idom(BlockId(0)), None);
+        assert_eq!(dominators.idom(BlockId(1)), Some(BlockId(0)));
+        assert_eq!(dominators.idom(BlockId(3)), Some(BlockId(0)));
+        assert_eq!(
+            dominators.children()[0],
+            vec![BlockId(1), BlockId(2), BlockId(3)]
+        );
+        let frontiers = dominators.frontiers(&function);
+        assert_eq!(frontiers[0], vec![]);
+        assert_eq!(frontiers[1], vec![BlockId(3)]);
+        assert_eq!(frontiers[2], vec![BlockId(3)]);
+    }
+
+    #[test]
+    fn promotes_slots_to_phis() {
+        // Both arms store to `x` and the join reads it.
+        let mut function = diamond();
+        function.slots.push(Slot {
+            name: "x".to_string(),
+            offset: 8,
+        });
+        function.blocks[1].insts.push(Inst::Store {
+            slot: SlotId(0),
+            src: VReg(1),
+        });
+        function.blocks[2].insts.push(Inst::Store {
+            slot: SlotId(0),
+            src: VReg(2),
+        });
+        function.blocks[3].insts[0] = Inst::Load {
+            dst: VReg(3),
+            slot: SlotId(0),
+        };
+        assert!(construct(&mut function));
+        assert_eq!(
+            function.to_string(),
+            "function f {\n\
+             bb0:\n  %6 = const 0\n  %0 = const 1\n  br %0, bb1, bb2\n\
+             bb1:\n  %1 = const 10\n  jmp bb3\n\
+             bb2:\n  %2 = const 20\n  jmp bb3\n\
+             bb3:\n  %5 = phi [bb1: %1], [bb2: %2]\n  %4 = add %5, %0\n  ret %4\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn removes_unreachable_blocks() {
+        let mut function = diamond();
+        function.blocks[0].term = Terminator::Jump(BlockId(1));
+        assert!(construct(&mut function));
+        assert_eq!(function.blocks.len(), 3);
+    }
+}
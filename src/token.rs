@@ -1,278 +1,566 @@
-#[derive(Debug, Clone)]
+use crate::diagnostic::Diagnostic;
+use crate::span::{FileId, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(&'static str),
+    Ident(String),
+    Punct(&'static str),
+    Num(i64),
+    Str(String),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
-    pub number: Option<i64>,
-    pub operator: Option<String>,
-    pub ident: Option<String>,
+    pub kind: TokenKind,
+    pub span: Span,
 }
 
-impl PartialEq for Token {
-    fn eq(&self, other: &Self) -> bool {
-        self.number == other.number && self.operator == other.operator
+/// Reserved words of C11 (6.4.1).
+const KEYWORDS: [&str; 44] = [
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "_Alignas",
+    "_Alignof",
+    "_Atomic",
+    "_Bool",
+    "_Complex",
+    "_Generic",
+    "_Imaginary",
+    "_Noreturn",
+    "_Static_assert",
+    "_Thread_local",
+];
+
+/// Punctuators understood by the lexer. Longer spellings come first so that
+/// `<=` is not read as `<` followed by `=`.
+const PUNCTS: [&str; 16] = [
+    "==", "!=", "<=", ">=", "+", "-", "*", "/", "(", ")", "{", "}", "<", ">", "=", ";",
+];
+
+impl Token {
+    fn new(kind: TokenKind, span: Span) -> Self {
+        Token { kind, span }
+    }
+
+    pub fn eof(span: Span) -> Self {
+        Token::new(TokenKind::Eof, span)
+    }
+
+    pub fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.kind, TokenKind::Punct(p) if p == punct)
+    }
+
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.kind, TokenKind::Keyword(k) if k == keyword)
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.kind == TokenKind::Eof
+    }
+
+    pub fn parse(file: FileId, input: &str) -> Result<Vec<Token>, Diagnostic> {
+        Lexer::new(file, input).collect()
     }
 }
 
-impl Token {
-    fn operator(op: String) -> Self {
-        Token {
-            number: None,
-            operator: Some(op),
-            ident: None,
+/// Splits source text into tokens in a single forward pass over its bytes.
+/// Yields an `Eof` token last, or stops after the first error.
+pub struct Lexer<'a> {
+    file: FileId,
+    input: &'a str,
+    position: usize,
+    line: usize,
+    line_start: usize,
+    done: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: FileId, input: &'a str) -> Self {
+        Lexer {
+            file,
+            input,
+            position: 0,
+            line: 1,
+            line_start: 0,
+            done: false,
         }
     }
 
-    fn number(num: i64) -> Self {
-        Token {
-            number: Some(num),
-            operator: None,
-            ident: None,
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.position).copied()
+    }
+
+    fn bump(&mut self) {
+        if self.peek() == Some(b'\n') {
+            self.line += 1;
+            self.line_start = self.position + 1;
         }
+        self.position += 1;
+    }
+
+    fn span_from(&self, offset: usize, line: usize, column: usize) -> Span {
+        Span::new(self.file, offset, self.position - offset, line, column)
     }
 
-    fn ident(ident: String) -> Self {
-        Token {
-            number: None,
-            operator: None,
-            ident: Some(ident),
+    fn consume_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c') = self.peek() {
+            self.bump();
         }
     }
 
-    pub fn parse(input: String) -> Vec<Token> {
-        let mut tokens: Vec<Token> = vec![];
-        let mut input = input;
+    fn consume_number(&mut self) -> Option<Result<TokenKind, String>> {
+        let start = self.position;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.bump();
+        }
+        if start == self.position {
+            None
+        } else {
+            match self.input[start..self.position].parse::<i64>() {
+                Ok(num) => Some(Ok(TokenKind::Num(num))),
+                Err(_) => Some(Err("integer constant is too large for its type".to_string())),
+            }
+        }
+    }
 
+    fn consume_string(&mut self) -> Option<Result<TokenKind, String>> {
+        if self.peek() != Some(b'"') {
+            return None;
+        }
+        self.bump();
+        let mut bytes: Vec<u8> = vec![];
         loop {
-            if input.is_empty() {
-                break;
-            }
-            consume_whitespace(&mut input);
-            if let Some(token) = consume_number(&mut input) {
-                tokens.push(token);
-                continue;
-            }
-            if let Some(token) = consume_operator(&mut input) {
-                tokens.push(token);
-                continue;
-            }
-            if let Some(token) = consume_ident(&mut input) {
-                tokens.push(token);
-                continue;
+            match self.peek() {
+                Some(b'"') => {
+                    self.bump();
+                    break;
+                }
+                Some(b'\\') => {
+                    self.bump();
+                    let byte = match self.peek() {
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(b'r') => b'\r',
+                        Some(b'0') => b'\0',
+                        Some(byte) => byte,
+                        None => {
+                            return Some(Err("missing terminating '\"' character".to_string()));
+                        }
+                    };
+                    bytes.push(byte);
+                    self.bump();
+                }
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.bump();
+                }
+                None => {
+                    return Some(Err("missing terminating '\"' character".to_string()));
+                }
             }
         }
-
-        return tokens;
+        // Escapes only ever replace ASCII bytes, so the contents stay UTF-8.
+        Some(Ok(TokenKind::Str(String::from_utf8(bytes).unwrap())))
     }
-}
 
-fn consume_whitespace(input: &mut String) {
-    loop {
-        match input.chars().next() {
-            Some(c) if c.is_whitespace() => {
-                input.remove(0);
-            }
-            _ => {
-                break;
+    fn consume_punct(&mut self) -> Option<TokenKind> {
+        let rest = &self.input.as_bytes()[self.position..];
+        for punct in PUNCTS.iter() {
+            if rest.starts_with(punct.as_bytes()) {
+                self.position += punct.len();
+                return Some(TokenKind::Punct(punct));
             }
         }
+        None
     }
-}
 
-fn consume_number(input: &mut String) -> Option<Token> {
-    let mut digits = "".to_string();
-    loop {
-        match input.chars().next() {
-            Some(c) if c.is_ascii_digit() => {
-                digits += &c.to_string();
-                input.remove(0);
-            }
+    /// Reads a maximal identifier and only then decides whether it is a
+    /// keyword, so that `returned` is an identifier rather than `return`
+    /// followed by `ed`.
+    fn consume_ident(&mut self) -> Option<TokenKind> {
+        match self.peek() {
+            Some(c) if is_ident_start(c) => {}
             _ => {
+                return None;
+            }
+        }
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !is_ident_continue(c) {
                 break;
             }
+            self.bump();
+        }
+        let ident = &self.input[start..self.position];
+        match KEYWORDS.iter().find(|keyword| **keyword == ident) {
+            Some(keyword) => Some(TokenKind::Keyword(keyword)),
+            None => Some(TokenKind::Ident(ident.to_string())),
         }
     }
-    if digits.is_empty() {
-        None
-    } else {
-        Some(Token::number(digits.parse::<i64>().unwrap()))
+
+    fn consume_stray(&mut self) -> String {
+        let c = self.input[self.position..].chars().next().unwrap();
+        self.position += c.len_utf8();
+        format!("stray '{}' in program", c)
     }
-}
 
-fn consume_operator(input: &mut String) -> Option<Token> {
-    if input.starts_with("return") {
-        let token = Some(Token::operator(input[..6].to_string()));
-        input.drain(0..6);
-        return token;
-    }
-    if input.starts_with("==")
-        || input.starts_with("!=")
-        || input.starts_with("<=")
-        || input.starts_with(">=")
-    {
-        let token = Some(Token::operator(input[..2].to_string()));
-        input.drain(0..2);
-        return token;
-    }
-    match input.chars().next() {
-        Some(c)
-            if c == '+'
-                || c == '-'
-                || c == '*'
-                || c == '/'
-                || c == '('
-                || c == ')'
-                || c == '>'
-                || c == '<'
-                || c == '='
-                || c == ';' =>
-        {
-            input.remove(0);
-            Some(Token::operator(c.to_string()))
-        }
-        _ => None,
+    #[cfg(test)]
+    fn rest(&self) -> &str {
+        &self.input[self.position..]
     }
 }
 
-fn consume_ident(input: &mut String) -> Option<Token> {
-    let mut chars = "".to_string();
-    loop {
-        match input.chars().next() {
-            Some(c) if c.is_ascii_alphabetic() => {
-                chars += &c.to_string();
-                input.remove(0);
-            }
-            _ => {
-                break;
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.consume_whitespace();
+        let offset = self.position;
+        let line = self.line;
+        let column = offset - self.line_start + 1;
+        if offset == self.input.len() {
+            self.done = true;
+            return Some(Ok(Token::eof(self.span_from(offset, line, column))));
+        }
+        let kind = if let Some(kind) = self.consume_number() {
+            kind
+        } else if let Some(kind) = self.consume_string() {
+            kind
+        } else if let Some(kind) = self.consume_punct() {
+            Ok(kind)
+        } else if let Some(kind) = self.consume_ident() {
+            Ok(kind)
+        } else {
+            Err(self.consume_stray())
+        };
+        let span = self.span_from(offset, line, column);
+        match kind {
+            Ok(kind) => Some(Ok(Token::new(kind, span))),
+            Err(message) => {
+                self.done = true;
+                Some(Err(Diagnostic::error(span, message)))
             }
         }
     }
-    if chars.is_empty() {
-        None
-    } else {
-        Some(Token::ident(chars))
-    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident_continue(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::token::consume_ident;
-    use crate::token::consume_number;
-    use crate::token::consume_operator;
-    use crate::token::consume_whitespace;
+    use crate::span::{FileId, Span};
+    use crate::token::Lexer;
     use crate::token::Token;
+    use crate::token::TokenKind;
 
     #[test]
     fn test_consume_whitespace() {
-        let mut input = "  ".to_string();
-        consume_whitespace(&mut input);
-        assert_eq!(input, "".to_string());
-        consume_whitespace(&mut input);
-        assert_eq!(input, "".to_string());
+        let mut lexer = Lexer::new(FileId(0), "  ");
+        lexer.consume_whitespace();
+        assert_eq!(lexer.rest(), "");
+        lexer.consume_whitespace();
+        assert_eq!(lexer.rest(), "");
     }
 
     #[test]
     fn test_consume_number() {
-        let mut input = "12+".to_string();
-        let output = consume_number(&mut input);
-        assert_eq!(output, Some(Token::number(12)));
-        assert_eq!(input, "+".to_string());
+        let mut lexer = Lexer::new(FileId(0), "12+");
+        let output = lexer.consume_number();
+        assert_eq!(output, Some(Ok(TokenKind::Num(12))));
+        assert_eq!(lexer.rest(), "+");
     }
 
     #[test]
     fn test_consume_ident() {
-        let mut input = "ab12".to_string();
-        let output = consume_ident(&mut input);
-        assert_eq!(output, Some(Token::ident("ab".to_string())));
-        assert_eq!(input, "12".to_string());
-        let output = consume_ident(&mut input);
+        let mut lexer = Lexer::new(FileId(0), "ab12+");
+        let output = lexer.consume_ident();
+        assert_eq!(output, Some(TokenKind::Ident("ab12".to_string())));
+        assert_eq!(lexer.rest(), "+");
+        let output = lexer.consume_ident();
+        assert_eq!(output, None);
+        assert_eq!(lexer.rest(), "+");
+
+        let mut lexer = Lexer::new(FileId(0), "12ab");
+        let output = lexer.consume_ident();
+        assert_eq!(output, None);
+        assert_eq!(lexer.rest(), "12ab");
+    }
+
+    #[test]
+    fn test_consume_keyword() {
+        let mut lexer = Lexer::new(FileId(0), "return 5");
+        let output = lexer.consume_ident();
+        assert_eq!(output, Some(TokenKind::Keyword("return")));
+        assert_eq!(lexer.rest(), " 5");
+
+        let mut lexer = Lexer::new(FileId(0), "_Static_assert(");
+        let output = lexer.consume_ident();
+        assert_eq!(output, Some(TokenKind::Keyword("_Static_assert")));
+        assert_eq!(lexer.rest(), "(");
+    }
+
+    #[test]
+    fn test_keyword_prefix_is_ident() {
+        let mut lexer = Lexer::new(FileId(0), "returned = 1");
+        let output = lexer.consume_ident();
+        assert_eq!(output, Some(TokenKind::Ident("returned".to_string())));
+        assert_eq!(lexer.rest(), " = 1");
+
+        let mut lexer = Lexer::new(FileId(0), "int_value");
+        let output = lexer.consume_ident();
+        assert_eq!(output, Some(TokenKind::Ident("int_value".to_string())));
+    }
+
+    #[test]
+    fn test_consume_punct() {
+        let mut lexer = Lexer::new(FileId(0), "+12");
+        let output = lexer.consume_punct();
+        assert_eq!(output, Some(TokenKind::Punct("+")));
+        assert_eq!(lexer.rest(), "12");
+
+        let mut lexer = Lexer::new(FileId(0), "<=12");
+        let output = lexer.consume_punct();
+        assert_eq!(output, Some(TokenKind::Punct("<=")));
+        assert_eq!(lexer.rest(), "12");
+
+        let mut lexer = Lexer::new(FileId(0), "return 5");
+        let output = lexer.consume_punct();
         assert_eq!(output, None);
-        assert_eq!(input, "12".to_string());
+        assert_eq!(lexer.rest(), "return 5");
     }
 
     #[test]
-    fn test_consume_operator() {
-        let mut input = "+12".to_string();
-        let output = consume_operator(&mut input);
-        assert_eq!(output, Some(Token::operator("+".to_string())));
-        assert_eq!(input, "12".to_string());
+    fn test_consume_string() {
+        let mut lexer = Lexer::new(FileId(0), "\"a\\\"b\\n\";");
+        let output = lexer.consume_string();
+        assert_eq!(output, Some(Ok(TokenKind::Str("a\"b\n".to_string()))));
+        assert_eq!(lexer.rest(), ";");
+    }
 
-        let mut input = "<=12".to_string();
-        let output = consume_operator(&mut input);
-        assert_eq!(output, Some(Token::operator("<=".to_string())));
-        assert_eq!(input, "12".to_string());
+    #[test]
+    fn eof() {
+        let output = Token::parse(FileId(0), "").unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].kind, TokenKind::Eof);
 
-        let mut input = "return 5".to_string();
-        let output = consume_operator(&mut input);
-        assert_eq!(output, Some(Token::operator("return".to_string())));
-        assert_eq!(input, " 5".to_string());
+        let output = Token::parse(FileId(0), "1 ").unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].kind, TokenKind::Num(1));
+        assert_eq!(output[1].kind, TokenKind::Eof);
+        assert_eq!(output[1].span, Span::new(FileId(0), 2, 0, 1, 3));
     }
 
     #[test]
     fn plus() {
         let input = "1 + 4";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(1));
-        assert_eq!(output[1], Token::operator("+".to_string()));
-        assert_eq!(output[2], Token::number(4));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(1));
+        assert_eq!(output[1].kind, TokenKind::Punct("+"));
+        assert_eq!(output[2].kind, TokenKind::Num(4));
     }
 
     #[test]
     fn minus() {
         let input = "3 - 2";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(3));
-        assert_eq!(output[1], Token::operator("-".to_string()));
-        assert_eq!(output[2], Token::number(2));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(3));
+        assert_eq!(output[1].kind, TokenKind::Punct("-"));
+        assert_eq!(output[2].kind, TokenKind::Num(2));
     }
 
     #[test]
     fn mul() {
         let input = "4 * 4";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(4));
-        assert_eq!(output[1], Token::operator("*".to_string()));
-        assert_eq!(output[2], Token::number(4));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(4));
+        assert_eq!(output[1].kind, TokenKind::Punct("*"));
+        assert_eq!(output[2].kind, TokenKind::Num(4));
     }
 
     #[test]
     fn div() {
         let input = "4 / 4";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(4));
-        assert_eq!(output[1], Token::operator("/".to_string()));
-        assert_eq!(output[2], Token::number(4));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(4));
+        assert_eq!(output[1].kind, TokenKind::Punct("/"));
+        assert_eq!(output[2].kind, TokenKind::Num(4));
     }
 
     #[test]
     fn plus_and_mul() {
         let input = "1 + 4 * 4 - 1";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(1));
-        assert_eq!(output[1], Token::operator("+".to_string()));
-        assert_eq!(output[2], Token::number(4));
-        assert_eq!(output[3], Token::operator("*".to_string()));
-        assert_eq!(output[4], Token::number(4));
-        assert_eq!(output[5], Token::operator("-".to_string()));
-        assert_eq!(output[6], Token::number(1));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(1));
+        assert_eq!(output[1].kind, TokenKind::Punct("+"));
+        assert_eq!(output[2].kind, TokenKind::Num(4));
+        assert_eq!(output[3].kind, TokenKind::Punct("*"));
+        assert_eq!(output[4].kind, TokenKind::Num(4));
+        assert_eq!(output[5].kind, TokenKind::Punct("-"));
+        assert_eq!(output[6].kind, TokenKind::Num(1));
     }
 
     #[test]
     fn brackets() {
         let input = "(1 + 4) * 2";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::operator("(".to_string()));
-        assert_eq!(output[1], Token::number(1));
-        assert_eq!(output[2], Token::operator("+".to_string()));
-        assert_eq!(output[3], Token::number(4));
-        assert_eq!(output[4], Token::operator(")".to_string()));
-        assert_eq!(output[5], Token::operator("*".to_string()));
-        assert_eq!(output[6], Token::number(2));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Punct("("));
+        assert_eq!(output[1].kind, TokenKind::Num(1));
+        assert_eq!(output[2].kind, TokenKind::Punct("+"));
+        assert_eq!(output[3].kind, TokenKind::Num(4));
+        assert_eq!(output[4].kind, TokenKind::Punct(")"));
+        assert_eq!(output[5].kind, TokenKind::Punct("*"));
+        assert_eq!(output[6].kind, TokenKind::Num(2));
     }
 
     #[test]
     fn two_digit() {
         let input = "15 + 40";
-        let output = Token::parse(input.to_string());
-        assert_eq!(output[0], Token::number(15));
-        assert_eq!(output[1], Token::operator("+".to_string()));
-        assert_eq!(output[2], Token::number(40));
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Num(15));
+        assert_eq!(output[1].kind, TokenKind::Punct("+"));
+        assert_eq!(output[2].kind, TokenKind::Num(40));
+    }
+
+    #[test]
+    fn keywords_and_idents() {
+        let input = "returned = 1; return returned;";
+        let output = Token::parse(FileId(0), input).unwrap();
+        assert_eq!(output[0].kind, TokenKind::Ident("returned".to_string()));
+        assert_eq!(output[1].kind, TokenKind::Punct("="));
+        assert_eq!(output[2].kind, TokenKind::Num(1));
+        assert_eq!(output[3].kind, TokenKind::Punct(";"));
+        assert_eq!(output[4].kind, TokenKind::Keyword("return"));
+        assert_eq!(output[5].kind, TokenKind::Ident("returned".to_string()));
+        assert_eq!(output[6].kind, TokenKind::Punct(";"));
+        assert_eq!(output[7].kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn spans() {
+        let input = "a = 10;\n  return a;";
+        let output = Token::parse(FileId(3), input).unwrap();
+        assert_eq!(output[0].span, Span::new(FileId(3), 0, 1, 1, 1));
+        assert_eq!(output[2].span, Span::new(FileId(3), 4, 2, 1, 5));
+        assert_eq!(output[3].span, Span::new(FileId(3), 6, 1, 1, 7));
+        assert_eq!(output[4].span, Span::new(FileId(3), 10, 6, 2, 3));
+        assert_eq!(output[5].span, Span::new(FileId(3), 17, 1, 2, 10));
+        assert_eq!(output[7].span, Span::new(FileId(3), 19, 0, 2, 12));
+    }
+
+    #[test]
+    fn errors() {
+        let output = Token::parse(FileId(0), "1 +\n @ 2");
+        let error = output.unwrap_err();
+        assert_eq!(error.message, "stray '@' in program");
+        assert_eq!(error.span, Span::new(FileId(0), 5, 1, 2, 2));
+
+        let output = Token::parse(FileId(0), "a = \"abc;");
+        let error = output.unwrap_err();
+        assert_eq!(error.message, "missing terminating '\"' character");
+        assert_eq!(error.span, Span::new(FileId(0), 4, 5, 1, 5));
+
+        let output = Token::parse(FileId(0), "99999999999999999999;");
+        let error = output.unwrap_err();
+        assert_eq!(error.message, "integer constant is too large for its type");
+        assert_eq!(error.span, Span::new(FileId(0), 0, 20, 1, 1));
+    }
+
+    #[test]
+    fn iterator() {
+        let mut lexer = Lexer::new(FileId(0), "1 @ 2");
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Num(1));
+        assert!(lexer.next().unwrap().is_err());
+        assert!(lexer.next().is_none());
+
+        let kinds: Vec<TokenKind> = Lexer::new(FileId(0), "a;")
+            .map(|token| token.unwrap().kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Ident("a".to_string()),
+                TokenKind::Punct(";"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn stray_multibyte_character() {
+        let error = Token::parse(FileId(0), "a = \u{3042};").unwrap_err();
+        assert_eq!(error.message, "stray '\u{3042}' in program");
+        assert_eq!(error.span, Span::new(FileId(0), 4, 3, 1, 5));
+    }
+
+    /// Benchmark: tokenizes generated programs of growing size and checks that
+    /// the time per byte stays flat. Run it with
+    /// `cargo test --release -- --ignored --nocapture bench_lexer`.
+    #[test]
+    #[ignore]
+    fn bench_lexer_scales_linearly() {
+        use std::time::Instant;
+
+        let statement = "abc_12 = (abc_12 + 12345) * 6 / 7 - -8;\nreturn abc_12 >= 9;\n";
+        let mut per_byte = vec![];
+        for megabytes in [1, 2, 4, 8].iter() {
+            let input = statement.repeat(megabytes * (1 << 20) / statement.len());
+            let start = Instant::now();
+            let tokens = Token::parse(FileId(0), &input).unwrap();
+            let elapsed = start.elapsed();
+            let nanos = elapsed.as_nanos() as f64 / input.len() as f64;
+            println!(
+                "{:>2} MB: {:>8} tokens in {:>10.3?} ({:.2} ns/byte)",
+                megabytes,
+                tokens.len(),
+                elapsed,
+                nanos
+            );
+            per_byte.push(nanos);
+        }
+        // A quadratic lexer would take 8x longer per byte on the largest input.
+        assert!(per_byte[3] < per_byte[0] * 3.0, "{:?}", per_byte);
     }
 }
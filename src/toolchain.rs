@@ -0,0 +1,129 @@
+//! Runs the system assembler, and the system C compiler as the linker.
+//! Executables are linked statically, and the compiler driver finds the C
+//! runtime start files and libraries.
+
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// Intermediate files of one run, deleted when this is dropped.
+pub struct TempFiles {
+    /// The directory holding the files, created on first use.
+    dir: Option<PathBuf>,
+    count: usize,
+}
+
+impl TempFiles {
+    pub fn new() -> Self {
+        TempFiles {
+            dir: None,
+            count: 0,
+        }
+    }
+
+    /// A fresh path ending in `extension`. The files of a run go in a new
+    /// directory of their own rather than straight into the shared
+    /// temporary directory, where their predictable names would let
+    /// another user plant a symlink for the tools to write through.
+    pub fn create(&mut self, extension: &str) -> Result<PathBuf, String> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = private_dir()?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        self.count += 1;
+        Ok(dir.join(format!("{}.{}", self.count, extension)))
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Creates a directory in the temporary directory that only the current
+/// user can access. Creating it fails if the name is taken, even by a
+/// symlink, so a name someone else got to first is skipped.
+fn private_dir() -> Result<PathBuf, String> {
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+    let mut attempt = 0;
+    loop {
+        let dir = env::temp_dir().join(format!("nine-cc-{}-{}", process::id(), attempt));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(error) => return Err(format!("cannot create '{}': {}", dir.display(), error)),
+        }
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(format!("{} failed", program)),
+        Err(error) => Err(format!("cannot run '{}': {}", program, error)),
+    }
+}
+
+/// Assembles `input` into the object file `output`.
+pub fn assemble(input: &Path, output: &Path) -> Result<(), String> {
+    run(Command::new("as").arg("-o").arg(output).arg(input))
+}
+
+/// Links `objects` into the static executable `output` with `$CC`, or `cc`
+/// if it is not set. `link_args` are the `-l` and `-L` options, in the
+/// order they were given.
+pub fn link(objects: &[PathBuf], link_args: &[String], output: &Path) -> Result<(), String> {
+    let cc = env::var_os("CC").unwrap_or_else(|| "cc".into());
+    let mut command = Command::new(cc);
+    command
+        .args(["-static", "-o"])
+        .arg(output)
+        .args(objects)
+        .args(link_args);
+    run(&mut command)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toolchain::TempFiles;
+    use std::fs;
+
+    #[test]
+    fn temp_files_are_removed() {
+        let mut temps = TempFiles::new();
+        let first = temps.create("s").unwrap();
+        let second = temps.create("o").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.extension().unwrap(), "s");
+        let dir = first.parent().unwrap().to_path_buf();
+        assert_eq!(second.parent(), Some(dir.as_path()));
+        fs::write(&first, "").unwrap();
+        drop(temps);
+        assert!(!first.exists());
+        assert!(!dir.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_dir_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut temps = TempFiles::new();
+        let path = temps.create("s").unwrap();
+        let metadata = fs::metadata(path.parent().unwrap()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Char,
+    Int,
+    Ptr(Box<Type>),
+}
+
+impl Type {
+    pub fn pointer_to(base: Type) -> Type {
+        Type::Ptr(Box::new(base))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::Char | Type::Int)
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Ptr(_))
+    }
+
+    /// Size in bytes. `int` is as wide as a register, since the generated
+    /// code does all arithmetic in 64 bits.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Char => 1,
+            Type::Int | Type::Ptr(_) => 8,
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        self.size()
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Char => write!(f, "char"),
+            Type::Int => write!(f, "int"),
+            Type::Ptr(base) => write!(f, "{} *", base),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::Type;
+
+    #[test]
+    fn display() {
+        assert_eq!(Type::Int.to_string(), "int");
+        assert_eq!(Type::pointer_to(Type::Char).to_string(), "char *");
+        assert_eq!(
+            Type::pointer_to(Type::pointer_to(Type::Char)).to_string(),
+            "char * *"
+        );
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!((Type::Char.size(), Type::Char.align()), (1, 1));
+        assert_eq!((Type::Int.size(), Type::Int.align()), (8, 8));
+        let ptr = Type::pointer_to(Type::Char);
+        assert_eq!((ptr.size(), ptr.align()), (8, 8));
+    }
+}
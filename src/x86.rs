@@ -0,0 +1,284 @@
+//! A structured form of the x86-64 assembly the code generator emits, so
+//! that it can be rewritten before it is printed in Intel syntax.
+
+use std::fmt;
+
+/// The general-purpose registers the code generator refers to by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    pub fn is_callee_saved(self) -> bool {
+        matches!(
+            self,
+            Reg::Rbx | Reg::Rbp | Reg::R12 | Reg::R13 | Reg::R14 | Reg::R15
+        )
+    }
+
+    /// The name of the low byte of the register.
+    fn byte_name(self) -> &'static str {
+        match self {
+            Reg::Rax => "al",
+            Reg::Rcx => "cl",
+            Reg::Rbx => "bl",
+            Reg::Rsp => "spl",
+            Reg::Rbp => "bpl",
+            Reg::Rsi => "sil",
+            Reg::Rdi => "dil",
+            Reg::R8 => "r8b",
+            Reg::R9 => "r9b",
+            Reg::R10 => "r10b",
+            Reg::R11 => "r11b",
+            Reg::R12 => "r12b",
+            Reg::R13 => "r13b",
+            Reg::R14 => "r14b",
+            Reg::R15 => "r15b",
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Reg::Rax => "rax",
+            Reg::Rcx => "rcx",
+            Reg::Rbx => "rbx",
+            Reg::Rsp => "rsp",
+            Reg::Rbp => "rbp",
+            Reg::Rsi => "rsi",
+            Reg::Rdi => "rdi",
+            Reg::R8 => "r8",
+            Reg::R9 => "r9",
+            Reg::R10 => "r10",
+            Reg::R11 => "r11",
+            Reg::R12 => "r12",
+            Reg::R13 => "r13",
+            Reg::R14 => "r14",
+            Reg::R15 => "r15",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    /// The quadword at `base + disp`.
+    Mem {
+        base: Reg,
+        disp: i32,
+    },
+    /// The address of a label, relative to `rip`. Only valid for `lea`.
+    Rip(String),
+}
+
+impl Operand {
+    pub fn is_mem(&self) -> bool {
+        matches!(self, Operand::Mem { .. })
+    }
+}
+
+/// A condition code, as in `sete` and `je`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    E,
+    Ne,
+    L,
+    Le,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Label(String),
+    Mov(Operand, Operand),
+    Lea(Reg, Operand),
+    Push(Operand),
+    Pop(Reg),
+    Add(Operand, Operand),
+    Sub(Operand, Operand),
+    Imul(Reg, Operand),
+    Cmp(Operand, Operand),
+    /// Sign-extends `rax` into `rdx`.
+    Cqo,
+    Idiv(Operand),
+    /// Shifts the register left by `cl`.
+    ShlCl(Reg),
+    /// Sets the low byte of the register from a condition.
+    Set(Cond, Reg),
+    /// Zero-extends the low byte of the second register into the first.
+    Movzb(Reg, Reg),
+    Jmp(String),
+    Jcc(Cond, String),
+    /// Calls a function by name, which need not be defined in the program.
+    Call(String),
+    Ret,
+}
+
+/// A read-only, NUL-terminated string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+    pub label: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub insts: Vec<Inst>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub data: Vec<Data>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    /// The number of instructions, not counting labels.
+    pub fn instruction_count(&self) -> usize {
+        self.functions
+            .iter()
+            .flat_map(|function| &function.insts)
+            .filter(|inst| !matches!(inst, Inst::Label(_)))
+            .count()
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Imm(value) => write!(f, "{}", value),
+            Operand::Mem { base, disp } if *disp < 0 => {
+                write!(f, "QWORD PTR [{} - {}]", base, -(*disp as i64))
+            }
+            Operand::Mem { base, disp } => write!(f, "QWORD PTR [{} + {}]", base, disp),
+            Operand::Rip(label) => write!(f, "[rip + {}]", label),
+        }
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::L => "l",
+            Cond::Le => "le",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Label(label) => write!(f, "{}:", label),
+            Inst::Mov(dst, src) => write!(f, "  mov {}, {}", dst, src),
+            Inst::Lea(dst, src) => write!(f, "  lea {}, {}", dst, src),
+            Inst::Push(src) => write!(f, "  push {}", src),
+            Inst::Pop(dst) => write!(f, "  pop {}", dst),
+            Inst::Add(dst, src) => write!(f, "  add {}, {}", dst, src),
+            Inst::Sub(dst, src) => write!(f, "  sub {}, {}", dst, src),
+            Inst::Imul(dst, src) => write!(f, "  imul {}, {}", dst, src),
+            Inst::Cmp(lhs, rhs) => write!(f, "  cmp {}, {}", lhs, rhs),
+            Inst::Cqo => write!(f, "  cqo"),
+            Inst::Idiv(src) => write!(f, "  idiv {}", src),
+            Inst::ShlCl(dst) => write!(f, "  shl {}, cl", dst),
+            Inst::Set(cond, dst) => write!(f, "  set{} {}", cond, dst.byte_name()),
+            Inst::Movzb(dst, src) => write!(f, "  movzb {}, {}", dst, src.byte_name()),
+            Inst::Jmp(label) => write!(f, "  jmp {}", label),
+            Inst::Jcc(cond, label) => write!(f, "  j{} {}", cond, label),
+            Inst::Call(name) => write!(f, "  call {}", name),
+            Inst::Ret => write!(f, "  ret"),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, ".intel_syntax noprefix")?;
+        if !self.data.is_empty() {
+            writeln!(f, ".section .rodata")?;
+            for data in &self.data {
+                let bytes: Vec<String> = data.bytes.iter().map(|byte| byte.to_string()).collect();
+                writeln!(f, "{}:", data.label)?;
+                writeln!(f, "  .byte {}", bytes.join(", "))?;
+            }
+        }
+        writeln!(f, ".text")?;
+        for function in &self.functions {
+            writeln!(f, ".global {}", function.name)?;
+            writeln!(f, "{}:", function.name)?;
+            for inst in &function.insts {
+                writeln!(f, "{}", inst)?;
+            }
+        }
+        // Tells the linker that the stack need not be executable.
+        writeln!(f, ".section .note.GNU-stack,\"\",@progbits")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::x86::{Cond, Data, Function, Inst, Operand, Program, Reg};
+
+    #[test]
+    fn display() {
+        let program = Program {
+            data: vec![Data {
+                label: ".LC0".to_string(),
+                bytes: vec![104, 105, 0],
+            }],
+            functions: vec![Function {
+                name: "main".to_string(),
+                insts: vec![
+                    Inst::Push(Operand::Reg(Reg::Rbp)),
+                    Inst::Label(".L.main.0".to_string()),
+                    Inst::Lea(Reg::Rax, Operand::Rip(".LC0".to_string())),
+                    Inst::Mov(
+                        Operand::Mem {
+                            base: Reg::Rbp,
+                            disp: -8,
+                        },
+                        Operand::Imm(3),
+                    ),
+                    Inst::Set(Cond::Le, Reg::R9),
+                    Inst::Movzb(Reg::Rax, Reg::Rax),
+                    Inst::Jcc(Cond::E, ".L.main.0".to_string()),
+                    Inst::Ret,
+                ],
+            }],
+        };
+        assert_eq!(
+            program.to_string(),
+            ".intel_syntax noprefix\n\
+             .section .rodata\n\
+             .LC0:\n  .byte 104, 105, 0\n\
+             .text\n\
+             .global main\n\
+             main:\n  push rbp\n\
+             .L.main.0:\n  lea rax, [rip + .LC0]\n  mov QWORD PTR [rbp - 8], 3\n\
+             \x20 setle r9b\n  movzb rax, al\n  je .L.main.0\n  ret\n\
+             .section .note.GNU-stack,\"\",@progbits\n"
+        );
+        assert_eq!(program.instruction_count(), 7);
+    }
+}
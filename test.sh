@@ -6,13 +6,67 @@ try() {
     expected="$1"
     input="$2"
 
-    ${ninecc} "$input" > test.s
-    gcc -static -o test test.s
-    ./test
+    for level in -O0 -O1 -O2; do
+        printf "%s" "$input" | ${ninecc} $level -o test -
+        ./test
+        actual="$?"
+
+        if [ "$actual" != "$expected" ]; then
+            echo "$input $expected, but got $actual with $level"
+            exit 1
+        fi
+
+        printf "%s" "$input" | ${ninecc} $level --jit -
+        actual="$?"
+
+        if [ "$actual" != "$expected" ]; then
+            echo "$input $expected, but got $actual with $level --jit"
+            exit 1
+        fi
+    done
+
+    printf "%s" "$input" | ${ninecc} --run -
     actual="$?"
 
     if [ "$actual" != "$expected" ]; then
-        echo "$input $expected, but got $actual"
+        echo "$input $expected, but got $actual with --run"
+        exit 1
+    fi
+}
+
+fail() {
+    expected="$1"
+    input="$2"
+
+    printf "%s" "$input" | ${ninecc} -S -o test.s - 2> test.err
+    actual="$?"
+
+    if [ "$actual" != 1 ]; then
+        echo "$input should fail, but exited with $actual"
+        exit 1
+    fi
+    if ! grep -qF "$expected" test.err; then
+        echo "$input should report \"$expected\", but got:"
+        cat test.err
+        exit 1
+    fi
+}
+
+warn() {
+    expected="$1"
+    input="$2"
+
+    printf "%s" "$input" | ${ninecc} "${@:3}" -S -o test.s - 2> test.err
+    actual="$?"
+
+    if [ "$actual" != 0 ]; then
+        echo "$input should compile, but exited with $actual"
+        cat test.err
+        exit 1
+    fi
+    if ! grep -qF "$expected" test.err; then
+        echo "$input should warn \"$expected\", but got:"
+        cat test.err
         exit 1
     fi
 }
@@ -36,6 +90,8 @@ try 0 '7 < 5;'
 try 1 '7 > 5;'
 try 0 '4 > 5;'
 try 0 '12+3 != 20-5;'
+try 1 '1 < 2 + 3;'
+try 0 '5 > 2 + 3;'
 try 15 'a = 5; b = 10; a + b;'
 try 1 '
 a = 10;
@@ -52,5 +108,111 @@ try 5 '
 return 5;
 return 10;
 '
+try 9 '((1+2)*3);'
+try 3 'a = 1; b = 2; (a+(b));'
+try 3 '(1+(2*3))-4;'
+try 7 '((((((7))))));'
+try 1 '((1 < (2)) == (3 >= ((2))));'
+try 6 '((1+2)*(3+4))/((3)) - 1;'
+try 6 '-((1)-(2-(3-(4)))) + 4;'
+try 3 'returned = 3; return returned;'
+try 7 'int_value = 7; _x1 = int_value; _x1;'
+
+fail "<stdin>:1:6: error: expected ';'" '1 + 2'
+fail "<stdin>:1:1: error: lvalue required as left operand of assignment" '1 = 2;'
+fail "<stdin>:1:5: error: stray '@' in program" 'a = @;'
+fail "<stdin>:1:12: error: expected expression" '1 + 2 b; + ;'
+try 3 's = "abc"; t = s + 3; t - s;'
+try 1 's = "abc"; t = s; s == t;'
+try 0 's = "abc"; "abc" == s;'
+try 2 's = "a\"b"; t = 1 + s + 1; t - s;'
+try 210 '1+(2+(3+(4+(5+(6+(7+(8+(9+(10+(11+(12+(13+(14+(15+(16+(17+(18+(19+20))))))))))))))))));'
+try 120 'a = 1; b = 2; c = 3; d = 4; e = 5; f = a * b * c * d * e; g = a + b + c + d + e; h = f / g * (a + b + c + d + e); h - 60 + f - 60;'
+try 1 'a = 4294967296 * 4294967296; b = 8589934592; a = b / 4294967296; a - 1;'
+
+# More locals than the 26 that used to fit in a fixed 208-byte frame.
+locals='x0 = 1;'
+for i in $(seq 1 299); do
+    locals="$locals x$i = x$((i - 1)) + 1;"
+done
+try 42 "$locals return x299 - 258;"
+
+fail "<stdin>:1:1: error: use of undeclared identifier 'a'" 'a + 1;'
+fail "<stdin>:1:5: error: use of undeclared identifier 'b'" 'a = b;'
+fail "<stdin>:1:10: error: invalid operands to binary * (have 'char *' and 'int')" 's = "x"; s * 2;'
+fail "<stdin>:1:8: error: incompatible types when assigning to type 'int' from type 'char *'" 'a = 1; a = "x";'
+fail "<stdin>:1:8: error: incompatible types when returning type 'char *' but 'int' was expected" 'return "x";'
+
+try 47 '5+6*7;'
+try 42 'a = 21; a * 2;'
+try 9 'a = 4; 2 * a + 1 * 1 + 0;'
+warn "<stdin>:1:18: warning: division by zero [-Wdiv-by-zero]" 'a = 1; return 0; a / 0;'
+warn "<stdin>:1:11: warning: code will never be executed [-Wunreachable-code]" 'return 3; 4; return 5;' -Wunreachable-code
+try 3 'return 3; 4; return 5;'
+
+echo '1 + 2;' | ${ninecc} --stats -S - > test.s 2> test.err
+if ! grep -qE "^instructions: [0-9]+ before peephole, [0-9]+ after$" test.err; then
+    echo "--stats should report instruction counts, but got:"
+    cat test.err
+    exit 1
+fi
+
+echo 'return 1;' | ${ninecc} --dump-tokens - | grep -q "^1:1     keyword return$" || { echo "--dump-tokens failed"; exit 1; }
+echo 'a = 1;' | ${ninecc} --dump-ast=json - | grep -qF '{"locals":[{"name":"a","type":"int",' || { echo "--dump-ast=json failed"; exit 1; }
+echo 'a = b;' | ${ninecc} --dump-ast=json - > test.s 2> test.err && { echo "--dump-ast should fail on errors"; exit 1; }
+grep -qF '"kind":"var","name":"b","id":null' test.s || { echo "--dump-ast should dump unchecked trees"; exit 1; }
+grep -qF "<stdin>:1:5: error: use of undeclared identifier 'b'" test.err || { cat test.err; exit 1; }
+
+echo 'a = 2; return a * 3;' | ${ninecc} -O1 --emit=ir - | grep -q "^  %[0-9]* = const 6$" || { echo "--emit=ir failed"; exit 1; }
+echo 'return 1;' | ${ninecc} --emit=dot - | grep -qF '"main.bb0" -> "main.bb1";' || { echo "--emit=dot failed"; exit 1; }
+printf 'a = 0; 1 / a;' | ${ninecc} --run - 2> test.err && { echo "--run should fail on division by zero"; exit 1; }
+grep -qF "<stdin>:1:8: error: division by zero" test.err || { cat test.err; exit 1; }
+
+# Files in, files out, like cc: -S writes foo.s, -c writes foo.o and
+# linking writes a.out, unless -o says otherwise.
+mkdir -p tmp-cli/tmp
+echo 'return 7;' > tmp-cli/seven.c
+echo 'return 8;' > tmp-cli/eight.c
+(cd tmp-cli && ../${ninecc} -S seven.c eight.c) || exit 1
+grep -q "^main:" tmp-cli/eight.s || { echo "eight.c should have been compiled to eight.s"; exit 1; }
+(cd tmp-cli && TMPDIR=tmp ../${ninecc} -c seven.c && ../${ninecc} eight.c && ./a.out)
+if [ "$?" != 8 ]; then
+    echo "eight.c should have been linked into a.out"
+    exit 1
+fi
+TMPDIR=tmp-cli/tmp ${ninecc} tmp-cli/seven.o -o test -L tmp-cli -lc || exit 1
+./test
+if [ "$?" != 7 ]; then
+    echo "seven.o should have been linked into test"
+    exit 1
+fi
+TMPDIR=tmp-cli/tmp ${ninecc} -fno-integrated-as -c tmp-cli/seven.c -o tmp-cli/seven-as.o || exit 1
+${ninecc} tmp-cli/seven-as.o -o test || exit 1
+./test
+if [ "$?" != 7 ]; then
+    echo "seven.c should have been assembled by as"
+    exit 1
+fi
+# The built-in linker needs neither ld nor libc, and links objects from as
+# as well as its own.
+(cd tmp-cli && TMPDIR=tmp ../${ninecc} -fuse-ld=builtin eight.c seven-as.o -o both) 2> test.err
+grep -qF "multiple definition of 'main'" test.err || { echo "main should be defined twice"; cat test.err; exit 1; }
+printf 's = "ab"; t = "c"; t - s;' > tmp-cli/strings.c
+TMPDIR=tmp-cli/tmp ${ninecc} -fuse-ld=builtin tmp-cli/strings.c -o test || exit 1
+./test
+if [ "$?" != 3 ]; then
+    echo "strings.c should have been linked by the built-in linker"
+    exit 1
+fi
+if [ -n "$(ls -A tmp-cli/tmp)" ]; then
+    echo "temporary files were left behind: $(ls tmp-cli/tmp)"
+    exit 1
+fi
+if ${ninecc} tmp-cli/missing.c 2> test.err; then
+    echo "a missing input should fail"
+    exit 1
+fi
+grep -qF "nine-cc: error: tmp-cli/missing.c:" test.err || { cat test.err; exit 1; }
+rm -rf tmp-cli
 
 echo OK